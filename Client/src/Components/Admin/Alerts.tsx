@@ -0,0 +1,47 @@
+import { useAlerts, useDismissAlert } from "../../Hooks/CtrlHooks";
+
+const Alerts = () => {
+    const { alerts, alertsLoading, alertsError } = useAlerts();
+    const { dismissAlert } = useDismissAlert();
+    if (alertsLoading) return <div>Loading...</div>;
+    if (alertsError) return <div>We encountered an error. Probably Server.</div>;
+
+    return (
+        <div className="p-2 bg-slate-300 m-4 rounded-sm shadow-md pb-1 dark:bg-zinc-800">
+            <h2 className="text-2xl text-center font-bold">Alerts</h2>
+            {alerts.length === 0 ? (
+                <p className="text-center pb-1">No checkers are broken.</p>
+            ) : (
+                <div className="bg-slate-400 p-2 rounded shadow-lg dark:bg-zinc-700">
+                    {alerts.map((alert) => (
+                        <div
+                            className="flex items-center bg-slate-200 p-2 rounded-md my-0.5 shadow-md dark:bg-zinc-500"
+                            key={alert.id}
+                        >
+                            <div className="flex-grow">
+                                <div>
+                                    <span className="font-bold">{alert.service}</span> on{" "}
+                                    <span className="font-bold">{alert.team}</span>
+                                    <span className="font-light text-sm">
+                                        {" "}
+                                        failed {alert.count} {alert.count === 1 ? "time" : "times"}, ticks{" "}
+                                        {alert.first_tick} to {alert.last_tick}
+                                    </span>
+                                </div>
+                                <pre className="whitespace-pre-wrap text-sm">{alert.message}</pre>
+                            </div>
+                            <button
+                                className="ml-2 bg-red-300 dark:bg-red-500 px-2 py-1 rounded shadow hover:shadow-lg active:shadow-none"
+                                onClick={() => dismissAlert(alert.id)}
+                            >
+                                Dismiss
+                            </button>
+                        </div>
+                    ))}
+                </div>
+            )}
+        </div>
+    );
+};
+
+export default Alerts;
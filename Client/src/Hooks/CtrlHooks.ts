@@ -5,6 +5,7 @@ import { useMutation, useQuery, useQueryClient } from "react-query";
 import { useNavigate, useLocation } from "react-router-dom";
 import {
     AdminInfo,
+    Alert,
     CreateInject,
     EnvPayload,
     Inject,
@@ -706,6 +707,42 @@ export const useAdminDeleteInject = () => {
     };
 }
 
+export const useAlerts = () => {
+    const { data, isLoading, error } = useQuery(
+        "alerts",
+        async () => {
+            const res = await axios.get("/admin/alerts");
+            return res.data;
+        },
+        {
+            refetchInterval: SCORE_REFETCH,
+        }
+    );
+    return {
+        alerts: data as Alert[],
+        alertsLoading: isLoading,
+        alertsError: error,
+    };
+}
+
+export const useDismissAlert = () => {
+    const queryClient = useQueryClient();
+    const { mutate } = useMutation(
+        async (id: string) => {
+            const res = await axios.delete(`/admin/alerts/${id}`);
+            return res.data;
+        },
+        {
+            onSuccess: () => {
+                queryClient.invalidateQueries("alerts");
+            },
+        }
+    );
+    return {
+        dismissAlert: mutate,
+    };
+}
+
 export const useLogin = () => {
   const { mutate, isError } = useMutation(async (data: any) => {
     const res = await axios.post('/login', data)
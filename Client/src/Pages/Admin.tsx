@@ -6,6 +6,7 @@ import Saves from "../Components/Admin/Saves";
 import Controls from "../Components/Admin/Controls";
 import Services from "../Components/Admin/Services";
 import Injects from "../Components/Admin/Injects";
+import Alerts from "../Components/Admin/Alerts";
 import { Link } from "react-router-dom";
 
 
@@ -28,6 +29,7 @@ const AdminPage = () => {
             <Link className="underline m-1" to="/">Back Home</Link>
             <h1 className="text-4xl text-center font-bold">Admin Page</h1>
             <Controls active={info.active} />
+            <Alerts />
             <Services />
             <Teams />
             <Injects />
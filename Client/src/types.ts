@@ -2,26 +2,219 @@ export interface ScoreWrapper {
     teams: [{
         name: string,
         score: number,
-        ups: boolean[]
+        up_ticks: number,
+        penalties: number,
+        ups: boolean[],
+        points: number[],
+        messages: (string | null)[]
     }],
     services: string[]
 }
 
 export interface Score {
     score: number,
+    points: number | null,
     up: boolean,
-    history: boolean[]
+    history: boolean[],
+    down_streak: number,
+    penalties: number,
+    checker_errors: number,
+    status: CheckStatus | null,
+    degraded: number,
+    message: string | null,
+    logins: Record<string, string>
+}
+
+export type CheckStatus = "up" | "degraded" | "down" | "timeout" | "checker_error";
+
+export interface Alert {
+    id: string,
+    team: string,
+    service: string,
+    message: string,
+    first_tick: number,
+    last_tick: number,
+    count: number
+}
+
+export interface Penalty {
+    service: string,
+    tick: number,
+    game_time: number,
+    points: number,
+    down_streak: number
 }
 
 export interface TeamScore {
     services: string[],
-    scores: Score[]
+    scores: Score[],
+    total: number,
+    up_ticks: number,
+    penalties: Penalty[],
+    errors: (string | null)[]
 }
 
 export interface Service {
     name: string,
     command: string,
-    multiplier: number
+    check?: NativeCheck,
+    exec?: string[],
+    interpreter?: "bash" | "sh" | "python" | "exec",
+    multiplier: number,
+    sla?: {
+        threshold: number,
+        penalty: number,
+        repeat: boolean
+    } | null,
+    timeout?: number,
+    schedule?: "every_tick" | { ticks: number } | { seconds: number },
+    retries?: number,
+    host?: string,
+    credentials?: Credentials | Credentials[],
+    env?: Record<string, string>,
+    sandbox?: Sandbox
+}
+
+export interface ServiceTemplate {
+    name: string,
+    description: string,
+    params: Record<string, TemplateParam>,
+    service: string | Omit<Service, 'name'>
+}
+
+export interface TemplateParam {
+    type: "string" | "host" | "port" | "integer" | "number" | "bool",
+    description: string,
+    default?: string | number | boolean
+}
+
+export interface TemplatePayload {
+    name?: string,
+    params: Record<string, string | number | boolean>
+}
+
+export interface Credentials {
+    group: string,
+    pick?: "random" | "round_robin" | "all"
+}
+
+export type NativeCheck =
+    | { http: HttpCheck }
+    | { dns: DnsCheck }
+    | { smtp: SmtpCheck }
+    | { pop3: MailboxCheck }
+    | { imap: MailboxCheck }
+    | { tcp: ScriptCheck }
+    | { udp: ScriptCheck }
+    | { mysql: DatabaseCheck }
+    | { postgres: DatabaseCheck }
+    | { ftp: FtpCheck }
+    | { tls: TlsCheck }
+    | { integrity: IntegrityCheck };
+
+export interface HttpCheck {
+    url: string,
+    method?: string,
+    headers?: Record<string, string>,
+    body?: string | null,
+    status?: number | number[] | null,
+    contains?: string | null,
+    regex?: string | null,
+    response_headers?: Record<string, string>,
+    json?: { path: string, equals?: unknown }[],
+    redirects?: number,
+    verify_tls?: boolean
+}
+
+export interface DnsCheck {
+    server: string,
+    name: string,
+    type?: 'A' | 'AAAA' | 'MX' | 'SRV' | 'TXT' | 'PTR',
+    expect?: string | string[] | null,
+    exact?: boolean,
+    tcp?: boolean
+}
+
+export interface MailboxCheck {
+    server: string,
+    banner?: string | null,
+    credentials?: string | null,
+    user?: string | null
+}
+
+export interface SmtpCheck extends MailboxCheck {
+    send?: {
+        from: string,
+        to: string,
+        confirm?: { imap: MailboxCheck } | { pop3: MailboxCheck } | null,
+        wait?: number
+    } | null
+}
+
+export type ScriptStep = (
+    | { connect: string }
+    | { send: string }
+    | { send_hex: string }
+    | { expect: string }
+    | { expect_hex: string }
+) & { timeout?: number };
+
+export interface ScriptCheck {
+    steps: ScriptStep[],
+    step_timeout?: number
+}
+
+export interface DatabaseCheck {
+    server: string,
+    credentials?: string | null,
+    user?: string | null,
+    database?: string | null,
+    query?: string | null,
+    expect?: (string | null)[][] | null,
+    exact?: boolean
+}
+
+export interface FtpCheck {
+    server: string,
+    banner?: string | null,
+    credentials?: string | null,
+    user?: string | null,
+    list?: string | null,
+    contains?: string | string[] | null,
+    retrieve?: string | null,
+    sha256?: string | null
+}
+
+export interface TlsCheck {
+    server: string,
+    hostname?: string | null,
+    min_days?: number,
+    min_key_bits?: number | null,
+    issuer?: string | null,
+    fingerprint?: string | string[] | null,
+    trusted?: boolean
+}
+
+export interface IntegrityCheck {
+    source: { http: HttpCheck } | { ftp: FtpCheck } | { command: string },
+    ignore?: string[],
+    on_change?: "down" | "degraded"
+}
+
+export interface Baseline {
+    hash: string,
+    size: number,
+    taken_at: number
+}
+
+export interface Sandbox {
+    user?: string,
+    group?: string,
+    cpu?: number,
+    memory?: number,
+    open_files?: number,
+    processes?: number,
+    read_only?: boolean
 }
 
 export interface AdminInfo {
@@ -29,15 +222,21 @@ export interface AdminInfo {
         name: string,
         env: [string,string][]
     }[],
+    env: [string,string][],
     services: Service[],
     active: boolean,
+    alerts: Alert[],
 }
 
 export interface TestResult {
     team: string,
     up: boolean,
+    status: CheckStatus,
+    fraction: number,
     message: string,
-    error: string
+    error: string,
+    summary: string | null,
+    metrics: { [name: string]: number }
 }
 
 export interface EnvPayload {
@@ -122,3 +321,13 @@ export interface TimeData {
     seconds: number,
     active: boolean,
 }
+
+export interface ExecutorStats {
+    queued: number,
+    running: number,
+    killed: number,
+    completed: number,
+    limit: number,
+    host_limit: number,
+    max_output: number
+}
@@ -1,11 +1,11 @@
-#/bin/bash
+#!/bin/bash
 
 #Argument 1: host, 2: domain, 3: username:password, 4 (optional): user directory
 
 #Check if ldapsearch is installed
 if ! [ -x "$(command -v ldapsearch)" ]; then
   echo 'Error: ldap-utils is not installed.' >&2
-  exit 1
+  exit 3
 fi
 
 #Split username and password
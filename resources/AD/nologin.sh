@@ -1,11 +1,11 @@
-#/bin/bash
+#!/bin/bash
 
 #Argument 1: host
 
 #Check if ldapsearch is installed
 if ! [ -x "$(command -v ldapsearch)" ]; then
   echo 'Error: ldap-utils is not installed.' >&2
-  exit 1
+  exit 3
 fi
 
 
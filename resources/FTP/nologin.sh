@@ -3,7 +3,7 @@
 #Make sure only 1 arg was passed
 if [ $# -ne 1 ]; then
     echo "Usage: $0 <host>"
-    exit 1
+    exit 3
 fi
 
 #Get the URL from arguments
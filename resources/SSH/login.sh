@@ -3,7 +3,7 @@
 #Check if 2 arg was passed
 if [ $# -ne 2 ]; then
     echo "Usage: $0 <host> <username>:<password>"
-    exit 1
+    exit 3
 fi
 
 #split the username and password
@@ -13,7 +13,7 @@ PASS=$(echo $2 | cut -d ':' -f 2)
 #Check if sshpass is installed
 if ! command -v sshpass &> /dev/null; then
     echo "This module requires sshpass to be installed."
-    exit 1
+    exit 3
 fi
 
 #Attempt connection to host with password.
@@ -3,13 +3,13 @@
 #Check if 1 arg was passed
 if [ $# -ne 1 ]; then
     echo "Usage: $0 <host>"
-    exit 1
+    exit 3
 fi
 
 #Check if sshpass is installed
 if ! command -v sshpass &> /dev/null; then
     echo "This module requires sshpass to be installed."
-    exit 1
+    exit 3
 fi
 
 #Attempt connection to host with password "nopassword" because it doesn't matter.
@@ -3,13 +3,13 @@
 #Check if 2 args were passed
 if [ $# -ne 2 ]; then
     echo "Usage: $0 <website> <search query>"
-    exit 1
+    exit 3
 fi
 
 #Check if curl is installed
 if ! command -v curl &> /dev/null; then
     echo "This module requires curl to be installed."
-    exit 1
+    exit 3
 fi
 
 #Curl the website
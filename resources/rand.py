@@ -8,13 +8,13 @@ if __name__ == '__main__':
     #check if there are enough arguments
     if len(args) != 2:
         print("Usage: ./rand.py <number>")
-        sys.exit(1)
+        sys.exit(3)
     #check if the argument is a number
     try:
         number = int(args[1])
     except ValueError:
         print("Error: argument is not a number")
-        sys.exit(1)
+        sys.exit(3)
     number = abs(number)
     rand = random.randint(1, number)
     print(rand)
@@ -1,12 +1,23 @@
+mod alert;
 pub mod config;
+mod context;
+mod env;
+mod executor;
+mod history;
 mod inject;
+mod native;
+mod output;
 mod password;
+mod report;
+mod sandbox;
 mod save;
 mod service;
+mod store;
 mod team;
+mod template;
 
 pub mod saves {
-    pub use super::save::{get_autosave_names, get_save_names, load_save, SaveError};
+    pub use super::save::{get_autosave_names, get_save_names, load_save};
 }
 pub mod passwords {
     pub use super::password::{
@@ -14,23 +25,110 @@ pub mod passwords {
         write_passwords, PasswordSave,
     };
 }
+pub mod baselines {
+    pub use super::native::Baseline;
+}
+pub mod alerts {
+    pub use super::alert::Alert;
+}
+pub mod histories {
+    pub use super::history::{HistoryPage, HistoryQuery};
+}
+pub mod outputs {
+    pub use super::output::ServiceOutputs;
+}
+pub mod executors {
+    pub use super::executor::{executor, ExecutorStats};
+}
+pub mod envs {
+    pub use super::env::is_reserved_name;
+}
+pub mod templates {
+    pub use super::template::{load_templates, ServiceTemplate};
+}
 pub mod injects {
     pub use super::inject::{CreateInject, Inject, InjectResponse, InjectUser};
 }
 
+use std::time::Duration;
+
 use serde::Serialize;
 
 pub use self::config::Config;
 pub use self::{
-    service::Service,
-    team::{Score, Team, TeamError},
+    service::{CheckStatus, Service},
+    team::{Penalty, Score, Team, TeamError},
 };
 
 
 pub fn resource_location() -> String {
+    #[cfg(test)]
+    if let Some(dir) = test_resources::dir() {
+        return dir;
+    }
     std::env::var("SB_RESOURCE_DIR").unwrap_or_else(|_| "resources".to_string())
 }
 
+/// Points the resource directory at an empty temporary one for tests that
+/// write files like passwords. Only the test's own thread sees it, so other
+/// tests still use the real resources.
+#[cfg(test)]
+pub mod test_resources {
+    use std::{cell::RefCell, fs, path::PathBuf};
+
+    thread_local! {
+        static DIR: RefCell<Option<String>> = const { RefCell::new(None) };
+    }
+
+    pub fn dir() -> Option<String> {
+        DIR.with(|dir| dir.borrow().clone())
+    }
+
+    /// Removes the directory and goes back to the real one when dropped.
+    pub struct TempResources {
+        path: PathBuf,
+    }
+
+    impl TempResources {
+        pub fn new() -> Self {
+            let path =
+                std::env::temp_dir().join(format!("scoreboard-resources-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&path).unwrap();
+            DIR.with(|dir| *dir.borrow_mut() = Some(path.to_string_lossy().into_owned()));
+            TempResources { path }
+        }
+    }
+
+    impl Drop for TempResources {
+        fn drop(&mut self) {
+            DIR.with(|dir| *dir.borrow_mut() = None);
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Time between score ticks. Set with SB_TICK in seconds.
+pub fn tick_duration() -> Duration {
+    let seconds = std::env::var("SB_TICK")
+        .ok()
+        .and_then(|tick| tick.parse().ok())
+        .filter(|tick| *tick > 0)
+        .unwrap_or(10);
+    Duration::from_secs(seconds)
+}
+
+/// Longest random delay before a check starts, so teams can't tell when in
+/// the tick they are being checked. Set with SB_JITTER in seconds. Checks
+/// all start at the beginning of the tick by default.
+pub fn check_jitter() -> Duration {
+    std::env::var("SB_JITTER")
+        .ok()
+        .and_then(|jitter| jitter.parse::<f64>().ok())
+        .filter(|jitter| jitter.is_finite() && *jitter > 0.0)
+        .map(Duration::from_secs_f64)
+        .unwrap_or_default()
+}
+
 #[derive(Serialize)]
 pub struct ScoreboardInfo {
     pub version: String
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use super::Config;
+
+/// Raised when a checker is broken so admins can fix it. Repeated errors
+/// for the same team and service update one alert instead of piling up.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Alert {
+    pub id: Uuid,
+    pub team: String,
+    pub service: String,
+    pub message: String,
+    pub first_tick: u64,
+    pub last_tick: u64,
+    /// How many checks have failed this way since the alert was raised
+    pub count: u32,
+}
+
+impl Config {
+    pub fn raise_alert(&mut self, team: &str, service: &str, message: String) {
+        let tick = self.tick();
+        warn!("Checker error for {} on {}: {}", service, team, message);
+        if let Some(alert) = self
+            .alerts
+            .iter_mut()
+            .find(|a| a.team == team && a.service == service)
+        {
+            alert.message = message;
+            alert.last_tick = tick;
+            alert.count += 1;
+            return;
+        }
+        self.alerts.push(Alert {
+            id: Uuid::new_v4(),
+            team: team.to_string(),
+            service: service.to_string(),
+            message,
+            first_tick: tick,
+            last_tick: tick,
+            count: 1,
+        });
+    }
+    pub fn dismiss_alert(&mut self, id: Uuid) -> bool {
+        let len = self.alerts.len();
+        self.alerts.retain(|a| a.id != id);
+        self.alerts.len() != len
+    }
+}
@@ -4,41 +4,85 @@ use std::collections::BTreeMap;
 use tracing::error;
 
 use std::fmt::Display;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use rand::seq::SliceRandom;
 use tokio::task::JoinSet;
 
+use super::history::{CheckHistory, CheckRecord};
 use super::inject::load_injects;
+use super::output::{sanitize_error, CheckOutput, OutputLog};
 use super::injects::Inject;
-use super::password::{load_password_saves, validate_password_fs};
+use super::password::{create_team_password_dir, load_password_saves, validate_password_fs};
 use super::save::{autosave, load_save, save_config, validate_save_fs, SaveError};
 use super::service::load_services;
-use super::team::load_teams;
-use super::{Score, Service, Team, TeamError};
+use super::team::{load_teams, TeamTemplate};
+use super::alert::Alert;
+use super::context::CheckContext;
+use super::env::{load_global_env, merge_env};
+use super::service::CheckStatus;
+use super::{check_jitter, tick_duration, Score, Service, Team, TeamError};
+
+/// Most teams one call to `generate_teams` can add
+pub const MAX_GENERATED_TEAMS: u32 = 500;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
     pub teams: BTreeMap<String, Team>,
+    /// Template from the teams config for making more teams
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team_template: Option<TeamTemplate>,
     pub services: Vec<Service>,
     pub injects: Vec<Inject>,
+    /// Variables every team gets, under the team's own. Saves from before
+    /// there were any get the ones in the file.
+    #[serde(default = "saved_global_env")]
+    pub env: Vec<(String, String)>,
     active: bool,
     #[serde(with = "serde_millis")]
     last_start: Instant,
     #[serde(with = "serde_millis")]
     game_time: Duration,
+    /// Number of score ticks run since the game was reset
+    #[serde(default)]
+    tick: u64,
+    /// Result of every check run since the game was reset
+    #[serde(default)]
+    pub history: CheckHistory,
+    /// Recent checker output for debugging. Not saved.
+    #[serde(skip)]
+    pub outputs: OutputLog,
+    /// Broken checkers that admins should look at
+    #[serde(default)]
+    pub alerts: Vec<Alert>,
+}
+
+/// The global variables for a save made before they were kept. A bad file
+/// shouldn't keep the save from loading, so it just leaves them out.
+fn saved_global_env() -> Vec<(String, String)> {
+    load_global_env().unwrap_or_else(|err| {
+        error!("Loading a save without shared variables: {}", err);
+        vec![]
+    })
 }
 
 impl Config {
     pub fn new() -> Self {
         let services = load_services();
-        let teams = load_teams(&services);
+        let (teams, team_template) = load_teams(&services);
         let injects = load_injects();
         let me = Config {
             teams,
+            team_template,
             services,
             injects,
+            env: load_global_env().unwrap_or_else(|err| panic!("{}", err)),
             active: true,
             last_start: Instant::now(),
             game_time: Duration::from_secs(0),
+            tick: 0,
+            history: CheckHistory::default(),
+            outputs: OutputLog::default(),
+            alerts: vec![],
             // to_delete: vec![],
         };
         validate_password_fs(&me);
@@ -49,14 +93,23 @@ impl Config {
         save_config(self, file_name)
     }
     pub fn autosave(&self) -> Result<(), SaveError> {
-        autosave(&self)
+        autosave(self)
     }
     pub fn from_save(file_name: &str) -> Result<Self, SaveError> {
         let mut save = load_save(file_name)?;
         load_password_saves(&save.passwords);
         save.config.active = false;
+        save.config.upgrade_save();
         Ok(save.config)
     }
+    /// Fills in what saves from older versions are missing.
+    fn upgrade_save(&mut self) {
+        for (name, team) in self.teams.iter_mut() {
+            // Teams added from the admin page used to be saved without a name
+            team.set_name(name.clone());
+            team.fill_in_points(&self.services);
+        }
+    }
     pub fn add_team(&mut self, name: String) -> Result<(), TeamError> {
         if name.is_empty() {
             return Err(TeamError::InvalidName);
@@ -65,16 +118,65 @@ impl Config {
             let var_name = Err(TeamError::AlreadyExists);
             return var_name;
         }
-        self.teams.insert(
-            name.clone(),
-            Team::from_services(&self.services),
-        );
-        validate_password_fs(self);
+        let mut team = Team::from_services(&self.services);
+        team.set_name(name.clone());
+        create_team_password_dir(&name);
+        self.teams.insert(name, team);
         Ok(())
     }
+    /// Adds `count` teams made from the team template, numbered after the
+    /// highest numbered team it already made. Returns the names of the new
+    /// teams. Nothing is added if any of them can't be.
+    pub fn generate_teams(&mut self, count: u32) -> Result<Vec<String>, ConfigError> {
+        let Some(template) = self.team_template.clone() else {
+            return Err(ConfigError::DoesNotExist);
+        };
+        if count == 0 || count > MAX_GENERATED_TEAMS || !template.is_numbered() {
+            return Err(ConfigError::BadValue);
+        }
+        let first = self
+            .teams
+            .keys()
+            .filter_map(|name| template.index_of(name))
+            .max()
+            .map_or(Some(template.start), |last| last.checked_add(1))
+            .ok_or(ConfigError::BadValue)?;
+        let last = first.checked_add(count - 1).ok_or(ConfigError::BadValue)?;
+        let teams: Vec<_> = (first..=last).map(|index| template.team(index, None)).collect();
+        if teams.iter().any(|(name, _)| self.teams.contains_key(name)) {
+            return Err(ConfigError::AlreadyExists);
+        }
+        let mut added = Vec::new();
+        for (name, env) in teams {
+            self.add_team(name.clone()).map_err(|_| ConfigError::BadValue)?;
+            if let Some(team) = self.teams.get_mut(&name) {
+                team.env = env;
+            }
+            added.push(name);
+        }
+        Ok(added)
+    }
     pub fn is_active(&self) -> bool {
         self.active
     }
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+    /// The global variables with the team's on top, for things like
+    /// inject templates
+    pub fn team_env(&self, team: &Team) -> Vec<(String, String)> {
+        merge_env(&self.env, &team.env, &BTreeMap::new())
+    }
+    /// Every variable a check of `service` gets for `team` besides the
+    /// built in ones
+    pub fn check_env(&self, team: &Team, service: &Service) -> Vec<(String, String)> {
+        merge_env(&self.env, &team.env, &service.env)
+    }
+    /// What a check of `service` run now would know about `team`
+    pub fn check_context(&self, team: &Team, service: &Service) -> CheckContext {
+        let env = self.check_env(team, service);
+        CheckContext::new(team, &service.name, env, self.tick, self.run_time().as_secs())
+    }
     pub fn run_time(&self) -> Duration {
         if self.active {
             self.game_time + (Instant::now() - self.last_start)
@@ -93,12 +195,19 @@ impl Config {
     pub fn reset_scores(&mut self) {
         self.active = false;
         self.game_time = Duration::from_secs(0);
+        self.tick = 0;
+        self.history = CheckHistory::default();
+        self.outputs = OutputLog::default();
+        self.alerts.clear();
         for team in self.teams.values_mut() {
             team.scores = self
                 .services
                 .iter()
                 .map(|s| (s.name.to_owned(), Score::default()))
                 .collect();
+            team.penalties.clear();
+            team.baselines.clear();
+            team.store.clear();
         }
     }
     pub async fn score_tick(&mut self) {
@@ -113,7 +222,7 @@ impl Config {
         }
     }
     pub fn add_service(&mut self, service: Service) -> Result<(), ConfigError> {
-        if let Some(_) = self.services.iter().find(|s| s.name == service.name) {
+        if self.services.iter().any(|s| s.name == service.name) {
             return Err(ConfigError::AlreadyExists);
         }
         if !service.is_valid() {
@@ -169,8 +278,29 @@ impl Config {
                         team.scores.insert(new_score_name, new_score);
                     }
                 }
+                team.penalties = other_team.penalties;
+                // Keep baselines an admin took during the tick
+                for (service, baseline) in other_team.baselines {
+                    let newer = team
+                        .baselines
+                        .get(&service)
+                        .is_none_or(|b| b.taken_at < baseline.taken_at);
+                    if newer {
+                        team.baselines.insert(service, baseline);
+                    }
+                }
+                team.store = other_team.store;
             });
         }
+        // Keep alerts dismissed during the tick dismissed
+        for alert in other.alerts {
+            if let Some(existing) = self.alerts.iter_mut().find(|a| a.id == alert.id) {
+                *existing = alert;
+            } else if alert.first_tick == other.tick {
+                self.alerts.push(alert);
+            }
+        }
+        self.tick = other.tick;
         // update injects
         for inject in other.injects {
             if let Some(index) = self.injects.iter().position(|i| i.uuid == inject.uuid) {
@@ -208,7 +338,11 @@ impl Display for Config {
         for (name, team) in self.teams.iter() {
             writeln!(f, "  {}:", name)?;
             for (service, score) in team.scores.iter() {
-                writeln!(f, "    {}: {} {}", service, score.up, score.score)?;
+                writeln!(
+                    f,
+                    "    {}: {} {} ({} points)",
+                    service, score.up, score.score, score.points()
+                )?;
             }
         }
         Ok(())
@@ -223,39 +357,202 @@ pub enum ConfigError {
 }
 
 async fn score_teams(config: &mut Config) {
+    config.tick += 1;
+    let tick = config.tick;
+    let game_time = config.run_time().as_secs();
     let services = &config.services;
     let mut set = JoinSet::new();
 
-    for (name, team) in &config.teams {
-        for check in services.iter().cloned() {
-            let env = team.env.clone();
-            let name = name.clone();
-            set.spawn(async move {
-                let Ok(output) = check.check_with_env(&env).await else {
-                    return None;
-                };
-                Some((name, check.name, output.up))
-            });
-        }
+    let mut checks: Vec<_> = config
+        .teams
+        .iter()
+        .flat_map(|(name, team)| {
+            services
+                .iter()
+                .filter(|s| s.schedule.is_due(tick))
+                .map(move |check| (name, team, check))
+        })
+        .collect();
+    // Every due check still runs once, just in an unpredictable order
+    checks.shuffle(&mut rand::thread_rng());
+    let jitter = check_jitter();
+    let tick_length = tick_duration();
+
+    for (name, team, check) in checks {
+        let env = config.check_env(team, check);
+        let context = CheckContext::new(team, &check.name, env, tick, game_time);
+        let name = name.clone();
+        let check = check.clone();
+        let baseline = team.baselines.get(&check.name).cloned();
+        let store = team.store.get(&check.name).cloned().unwrap_or_default();
+        let logins = team
+            .scores
+            .get(&check.name)
+            .map(|score| score.logins.clone())
+            .unwrap_or_default();
+        let delay = check.start_delay(jitter, tick_length);
+        set.spawn(async move {
+            tokio::time::sleep(delay).await;
+            let output = check
+                .check_with_env(&context, baseline.as_ref(), &store, &logins)
+                .await;
+            (name, check, output)
+        });
     }
 
     while let Some(res) = set.join_next().await {
-        let Ok(res) = res else {
+        let Ok((team_name, service, output)) = res else {
             continue;
         };
-        if let Some((team_name, service_name, up)) = res {
-            config.teams.entry(team_name).and_modify(|team| {
-                team.scores.entry(service_name).and_modify(|score| {
-                    score.up = up;
-                    if up {
-                        score.score += 1;
-                    }
-                    score.history.push_front(up);
-                    if score.history.len() > 10 {
-                        score.history.pop_back();
-                    }
-                });
-            });
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        config.history.push(CheckRecord {
+            team: team_name.clone(),
+            service: service.name.clone(),
+            tick,
+            time,
+            up: output.status.is_up(),
+            status: output.status,
+            fraction: output.fraction,
+            message: output.summary.clone(),
+            metrics: output.metrics.clone(),
+            duration: output.duration.as_millis() as u64,
+            exit_code: output.exit_code,
+            logins: output.logins.clone(),
+        });
+        if output.status == CheckStatus::CheckerError {
+            let mut message = sanitize_error(&output.error, &[]);
+            if message.is_empty() {
+                message = match output.exit_code {
+                    Some(code) => format!("Checker exited with code {}", code),
+                    None => "Checker failed without output".to_string(),
+                };
+            }
+            config.raise_alert(&team_name, &service.name, message);
+        }
+        config.teams.entry(team_name.clone()).and_modify(|team| {
+            let penalty = team
+                .scores
+                .get_mut(&service.name)
+                .and_then(|score| score.record(&service, &output, tick, game_time));
+            if let Some(penalty) = penalty {
+                team.penalties.push(penalty);
+            }
+            if let Some(baseline) = &output.baseline {
+                team.baselines.insert(service.name.clone(), baseline.clone());
+            }
+            if let Some(store) = &output.store {
+                team.store.insert(service.name.clone(), store.clone());
+            }
+        });
+        config.outputs.push(
+            &team_name,
+            &service.name,
+            CheckOutput {
+                tick,
+                time,
+                status: output.status,
+                exit_code: output.exit_code,
+                stdout: output.message,
+                stderr: output.error,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::{passwords::write_passwords, save::Save, test_resources::TempResources};
+
+    fn empty_config(services: Vec<Service>) -> Config {
+        Config {
+            teams: BTreeMap::new(),
+            team_template: None,
+            services,
+            injects: vec![],
+            env: vec![],
+            active: true,
+            last_start: Instant::now(),
+            game_time: Duration::ZERO,
+            tick: 0,
+            history: CheckHistory::default(),
+            outputs: OutputLog::default(),
+            alerts: vec![],
+        }
+    }
+    #[tokio::test]
+    async fn check_an_added_team() {
+        let mut service = Service::new(
+            "ssh".to_string(),
+            r#"[ "$SB_USER" = alice ] && echo "$TEAM_NAME""#.to_string(),
+            1.0,
+        );
+        service.credentials = serde_json::from_str(r#"{"group": "ssh"}"#).unwrap();
+        let _resources = TempResources::new();
+        let mut config = empty_config(vec![service.clone()]);
+        let name = "added".to_string();
+        assert!(config.add_team(name.clone()).is_ok());
+        write_passwords(&name, &"ssh".to_string(), "alice:pw").unwrap();
+
+        let team = &config.teams[&name];
+        let context = config.check_context(team, &service);
+        let output = service
+            .check_with_env(&context, None, &Default::default(), &BTreeMap::new())
+            .await;
+        assert_eq!(output.status, CheckStatus::Up, "{}", output.error);
+        assert_eq!(output.message.trim(), name);
+    }
+    #[test]
+    fn load_a_save_without_points() {
+        let score = r#"{"score": 3, "up": true, "history": [true, true, true]}"#;
+        let save = format!(
+            r#"{{"saved_at": 0, "passwords": {{}}, "config": {{
+                "teams": {{"team1": {{"name": "team1", "id": "{}", "env": [],
+                    "inject_responses": [], "scores": {{"web": {}, "old": {}}}}}}},
+                "services": [{{"name": "web", "command": "true", "multiplier": 2.5}}],
+                "injects": [], "env": [], "active": true,
+                "last_start": 0, "game_time": 0}}}}"#,
+            Uuid::new_v4(),
+            score,
+            score
+        );
+        let mut config = serde_json::from_str::<Save>(&save).unwrap().config;
+        config.upgrade_save();
+        let team = &config.teams["team1"];
+        assert_eq!(team.scores["web"].points, Some(7.5));
+        // Services no longer in the config count once per tick
+        assert_eq!(team.scores["old"].points, Some(3.0));
+        assert_eq!(team.score(), 10.5);
+        // A fresh score has no points yet and still saves and loads
+        let saved = serde_json::to_string(&Score::default()).unwrap();
+        assert_eq!(serde_json::from_str::<Score>(&saved).unwrap().points, None);
+    }
+    #[test]
+    fn generate_after_the_last_team() {
+        let _resources = TempResources::new();
+        let prefix = "gen-";
+        let mut config = empty_config(vec![]);
+        config.team_template = Some(
+            serde_yaml::from_str(&format!(
+                "name: {}{{{{index}}}}\nenv: {{HOST: '10.0.{{{{index}}}}.1'}}",
+                prefix
+            ))
+            .unwrap(),
+        );
+        for index in [1, 3] {
+            let name = format!("{}{}", prefix, index);
+            config.teams.insert(name.clone(), Team::from_services(&[]));
         }
+        assert!(config.generate_teams(0).is_err());
+        assert!(config.generate_teams(MAX_GENERATED_TEAMS + 1).is_err());
+        let added = config.generate_teams(2).unwrap();
+        assert_eq!(added, [format!("{}4", prefix), format!("{}5", prefix)]);
+        let team = &config.teams[&added[0]];
+        assert_eq!(team.name, added[0]);
+        assert_eq!(team.env, [("HOST".to_string(), "10.0.4.1".to_string())]);
+        assert_eq!(config.teams.len(), 4);
     }
 }
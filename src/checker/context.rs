@@ -0,0 +1,110 @@
+use std::{collections::BTreeMap, fs};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::{resource_location, Score, Team};
+
+/// Environment variable with the path of the check's context file.
+pub const CONTEXT_FILE_VAR: &str = "SB_CONTEXT_FILE";
+
+/// Variables every check gets from the game, in the order of
+/// `CheckContext::builtin_vars`.
+pub const BUILTIN_VARS: [&str; 6] = [
+    "TEAM_NAME",
+    "TEAM_ID",
+    "SERVICE_NAME",
+    "TICK",
+    "GAME_MINUTE",
+    "RESOURCE_DIR",
+];
+
+/// What a check knows about the game and the team it is checking. Checkers
+/// get it as variables, and process checkers also as a JSON file.
+#[derive(Serialize, Clone, Debug)]
+pub struct CheckContext {
+    pub team: String,
+    pub team_id: Uuid,
+    pub service: String,
+    pub tick: u64,
+    /// Whole minutes of game time
+    pub game_minute: u64,
+    /// Absolute path of the resources directory
+    pub resource_dir: String,
+    /// The team's variables, merged with the global and service ones
+    pub env: Vec<(String, String)>,
+    /// The team's scores, by service
+    pub scores: BTreeMap<String, Score>,
+}
+
+impl CheckContext {
+    /// `env` is every variable the team has for the service and
+    /// `game_time` is in seconds.
+    pub fn new(
+        team: &Team,
+        service: &str,
+        env: Vec<(String, String)>,
+        tick: u64,
+        game_time: u64,
+    ) -> Self {
+        let resource_dir = resource_location();
+        let resource_dir = fs::canonicalize(&resource_dir)
+            .map(|dir| dir.to_string_lossy().to_string())
+            .unwrap_or(resource_dir);
+        Self {
+            team: team.name.clone(),
+            team_id: team.id,
+            service: service.to_string(),
+            tick,
+            game_minute: game_time / 60,
+            resource_dir,
+            env,
+            scores: team.scores.clone(),
+        }
+    }
+    /// The variables every check gets besides the team's own.
+    pub fn builtin_vars(&self) -> Vec<(String, String)> {
+        let values = [
+            self.team.clone(),
+            self.team_id.to_string(),
+            self.service.clone(),
+            self.tick.to_string(),
+            self.game_minute.to_string(),
+            self.resource_dir.clone(),
+        ];
+        BUILTIN_VARS
+            .into_iter()
+            .zip(values)
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    }
+    /// The team's variables followed by the built in ones. Later values
+    /// win, so nothing can change what the built in ones say.
+    pub fn env(&self) -> Vec<(String, String)> {
+        let mut env = self.env.clone();
+        env.extend(self.builtin_vars());
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn builtins_win() {
+        let mut team = Team::from_services(&[]);
+        team.name = "team1".to_string();
+        let env = vec![
+            ("HOST".to_string(), "10.0.0.1".to_string()),
+            ("TEAM_NAME".to_string(), "team2".to_string()),
+        ];
+        let context = CheckContext::new(&team, "web", env, 12, 150);
+        let env: BTreeMap<_, _> = context.env().into_iter().collect();
+        assert_eq!(env["HOST"], "10.0.0.1");
+        assert_eq!(env["TEAM_NAME"], "team1");
+        assert_eq!(env["TEAM_ID"], team.id.to_string());
+        assert_eq!(env["SERVICE_NAME"], "web");
+        assert_eq!(env["TICK"], "12");
+        assert_eq!(env["GAME_MINUTE"], "2");
+    }
+}
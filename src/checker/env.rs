@@ -0,0 +1,208 @@
+use std::{collections::BTreeMap, fs};
+
+use tracing::warn;
+
+use super::{context::BUILTIN_VARS, resource_location};
+
+/// Whether `name` can be used as `$name`.
+pub fn is_valid_name(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+}
+
+/// Whether the scoreboard sets `name` itself for checkers, so it can't be
+/// a shared or service variable. That is the built in game variables and
+/// anything starting with `SB_`, like the login and file variables.
+pub fn is_reserved_name(name: &str) -> bool {
+    name.starts_with("SB_") || BUILTIN_VARS.contains(&name)
+}
+
+/// Replaces `{{key}}` (spaces inside the braces are fine) with what
+/// `lookup` gives for the key. Keys it doesn't know are left alone.
+pub fn fill_placeholders(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find("{{") {
+        let Some(close) = rest[open..].find("}}").map(|close| open + close) else {
+            break;
+        };
+        result.push_str(&rest[..open]);
+        match lookup(rest[open + 2..close].trim()) {
+            Some(value) => result.push_str(&value),
+            None => result.push_str(&rest[open..close + 2]),
+        }
+        rest = &rest[close + 2..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Reads `NAME=value` lines like a `.env` file. Blank lines, comments and
+/// `export` are skipped, and quotes around a value are removed.
+pub fn parse_dotenv(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut env: Vec<(String, String)> = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(format!("line {} is not NAME=value", number + 1));
+        };
+        let name = name.trim();
+        if !is_valid_name(name) {
+            return Err(format!("line {} has a bad name {:?}", number + 1, name));
+        }
+        let value = value.trim();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) if value.len() > 1 && value.ends_with(quote) => {
+                &value[1..value.len() - 1]
+            }
+            _ => value.split(" #").next().unwrap_or_default().trim_end(),
+        };
+        set_var(&mut env, name, value.to_string());
+    }
+    Ok(env)
+}
+
+/// Loads the global variables every team gets. They come from `.env` in
+/// the resources directory, or the file named by SB_ENV. It's fine for the
+/// file to be missing. Reserved names in it are skipped with a warning.
+pub fn load_global_env() -> Result<Vec<(String, String)>, String> {
+    let env_file = std::env::var("SB_ENV").unwrap_or_else(|_| ".env".to_string());
+    let Ok(file) = fs::read_to_string(format!("{}/{}", resource_location(), env_file)) else {
+        return Ok(vec![]);
+    };
+    let mut env = parse_dotenv(&file)
+        .map_err(|err| format!("{} is not formatted correctly: {}", env_file, err))?;
+    env.retain(|(name, _)| {
+        let reserved = is_reserved_name(name);
+        if reserved {
+            warn!("Skipping {} in {}, the scoreboard sets it itself", name, env_file);
+        }
+        !reserved
+    });
+    Ok(env)
+}
+
+/// Sets a variable, keeping its place if it was already set.
+fn set_var(env: &mut Vec<(String, String)>, name: &str, value: String) {
+    match env.iter_mut().find(|(k, _)| k == name) {
+        Some((_, old)) => *old = value,
+        None => env.push((name.to_string(), value)),
+    }
+}
+
+/// Layers a team's variables over the global ones, and a service's over
+/// both. Service values can use `$VAR` from the layers below.
+pub fn merge_env(
+    global: &[(String, String)],
+    team: &[(String, String)],
+    service: &BTreeMap<String, String>,
+) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    for (name, value) in global.iter().chain(team) {
+        set_var(&mut env, name, value.clone());
+    }
+    let service: Vec<_> = service
+        .iter()
+        .map(|(name, value)| (name, substitute(value, &env)))
+        .collect();
+    for (name, value) in service {
+        set_var(&mut env, name, value);
+    }
+    env
+}
+
+/// Replaces `$VAR` and `${VAR}` with values from `env`. Unknown variables
+/// become empty strings like they would in bash. `$$` is a literal `$`.
+pub fn substitute(template: &str, env: &[(String, String)]) -> String {
+    let lookup = |name: &str| {
+        env.iter()
+            .rev()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+            .unwrap_or("")
+    };
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(pos) = rest.find('$') {
+        result.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        if let Some(after) = after.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+        } else if let Some(braced) = after.strip_prefix('{') {
+            let Some(end) = braced.find('}') else {
+                result.push_str(&rest[pos..]);
+                return result;
+            };
+            result.push_str(lookup(&braced[..end]));
+            rest = &braced[end + 1..];
+        } else {
+            let end = after
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(after.len());
+            if end == 0 || after.starts_with(|c: char| c.is_ascii_digit()) {
+                result.push('$');
+                rest = after;
+            } else {
+                result.push_str(lookup(&after[..end]));
+                rest = &after[end..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn substitute_variables() {
+        let env = vec![
+            ("HOST".to_string(), "10.0.1.5".to_string()),
+            ("PORT".to_string(), "22".to_string()),
+        ];
+        assert_eq!(substitute("$HOST:${PORT}", &env), "10.0.1.5:22");
+        assert_eq!(substitute("${HOST}x $MISSING.", &env), "10.0.1.5x .");
+        assert_eq!(substitute("cost $$5 or $", &env), "cost $5 or $");
+        assert_eq!(substitute("${HOST", &env), "${HOST");
+    }
+    #[test]
+    fn dotenv_files() {
+        let env = parse_dotenv(
+            "# shared\nFLAG_SERVER=\"10.0.0.2\"\nexport DOMAIN='corp.local'\n\nTAG=a #b\nTAG=c=d",
+        )
+        .unwrap();
+        let env: Vec<_> = env.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            env,
+            [("FLAG_SERVER", "10.0.0.2"), ("DOMAIN", "corp.local"), ("TAG", "c=d")]
+        );
+        assert!(parse_dotenv("JUST A LINE").is_err());
+        assert!(parse_dotenv("1BAD=x").is_err());
+        assert!(is_reserved_name("SB_PASSWORD"));
+        assert!(is_reserved_name("TEAM_NAME"));
+        assert!(!is_reserved_name("TEAM_PASSWORD"));
+    }
+    #[test]
+    fn layers_win_in_order() {
+        let pairs = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        let global = pairs(&[("DOMAIN", "corp.local"), ("HOST", "0.0.0.0")]);
+        let team = pairs(&[("HOST", "10.0.1.5")]);
+        let service = [("URL".to_string(), "http://$HOST.$DOMAIN:8080".to_string())].into();
+        assert_eq!(
+            merge_env(&global, &team, &service),
+            pairs(&[
+                ("DOMAIN", "corp.local"),
+                ("HOST", "10.0.1.5"),
+                ("URL", "http://10.0.1.5.corp.local:8080")
+            ])
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    os::unix::process::CommandExt,
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::{OwnedSemaphorePermit, Semaphore, SemaphorePermit},
+    task::JoinHandle,
+    time::{timeout, timeout_at, Instant},
+};
+use tracing::{debug, warn};
+
+/// Runs checker processes with a limit on how many run at once, both overall
+/// and against a single host, and cleans up after the ones that hang.
+pub struct Executor {
+    global: Semaphore,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+    limit: usize,
+    host_limit: usize,
+    max_output: usize,
+    queued: AtomicU64,
+    running: AtomicU64,
+    killed: AtomicU64,
+    completed: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct ExecutorStats {
+    /// Checks waiting for a free slot
+    pub queued: u64,
+    pub running: u64,
+    /// Checks killed for running past their timeout
+    pub killed: u64,
+    pub completed: u64,
+    pub limit: usize,
+    pub host_limit: usize,
+    /// Bytes of stdout and stderr kept from each check
+    pub max_output: usize,
+}
+
+pub struct ProcessOutput {
+    /// None if the process was killed
+    pub status: Option<ExitStatus>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub timed_out: bool,
+}
+
+fn env_limit(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|limit| limit.parse().ok())
+        .filter(|limit| *limit > 0)
+        .unwrap_or(default)
+}
+
+/// The executor shared by every check. Limits are read once from
+/// SB_MAX_CHECKS, SB_MAX_CHECKS_PER_HOST and SB_MAX_OUTPUT.
+pub fn executor() -> &'static Executor {
+    static EXECUTOR: OnceLock<Executor> = OnceLock::new();
+    EXECUTOR.get_or_init(|| {
+        Executor::new(
+            env_limit("SB_MAX_CHECKS", 64),
+            env_limit("SB_MAX_CHECKS_PER_HOST", 8),
+            env_limit("SB_MAX_OUTPUT", 64 * 1024),
+        )
+    })
+}
+
+/// How long to wait for output after killing a process group. Only matters
+/// if something escaped the group while holding on to the pipes.
+const KILL_GRACE: Duration = Duration::from_secs(1);
+
+impl Executor {
+    pub fn new(limit: usize, host_limit: usize, max_output: usize) -> Self {
+        Executor {
+            global: Semaphore::new(limit),
+            hosts: Mutex::new(HashMap::new()),
+            limit,
+            host_limit,
+            max_output,
+            queued: AtomicU64::new(0),
+            running: AtomicU64::new(0),
+            killed: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+        }
+    }
+    pub fn stats(&self) -> ExecutorStats {
+        ExecutorStats {
+            queued: self.queued.load(Ordering::Relaxed),
+            running: self.running.load(Ordering::Relaxed),
+            killed: self.killed.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            limit: self.limit,
+            host_limit: self.host_limit,
+            max_output: self.max_output,
+        }
+    }
+    /// Bytes kept from a check's output
+    pub fn max_output(&self) -> usize {
+        self.max_output
+    }
+    fn host_semaphore(&self, host: &str) -> Arc<Semaphore> {
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        hosts
+            .entry(host.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.host_limit)))
+            .clone()
+    }
+    /// Waits for a slot under the global limit and the host's limit.
+    async fn acquire(
+        &self,
+        host: Option<&str>,
+    ) -> (SemaphorePermit<'_>, Option<OwnedSemaphorePermit>) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        // Wait on the host first so checks queued for a busy host don't hold
+        // global slots other hosts could use
+        let host = match host {
+            Some(host) => self.host_semaphore(host).acquire_owned().await.ok(),
+            None => None,
+        };
+        let global = self.global.acquire().await;
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        (global.expect("semaphore is never closed"), host)
+    }
+    /// Runs a check done by the scoreboard itself under the same limits as
+    /// checker processes. None if it timed out.
+    pub async fn run_native<F: Future>(
+        &self,
+        host: Option<&str>,
+        check: F,
+        limit: Duration,
+    ) -> Option<F::Output> {
+        let _permits = self.acquire(host).await;
+        self.running.fetch_add(1, Ordering::Relaxed);
+        let output = timeout(limit, check).await.ok();
+        if output.is_none() {
+            self.killed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.running.fetch_sub(1, Ordering::Relaxed);
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        output
+    }
+    /// Waits for a free slot, then runs the command in its own process
+    /// group. The timeout only starts once the process is running. If it
+    /// runs too long the whole group is killed.
+    pub async fn run(
+        &self,
+        host: Option<&str>,
+        mut command: Command,
+        limit: Duration,
+    ) -> std::io::Result<ProcessOutput> {
+        let _permits = self.acquire(host).await;
+
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0);
+        let mut child = tokio::process::Command::from(command)
+            .kill_on_drop(true)
+            .spawn()?;
+        self.running.fetch_add(1, Ordering::Relaxed);
+        let pid = child.id();
+        let mut stdout = read_capped(child.stdout.take(), self.max_output);
+        let mut stderr = read_capped(child.stderr.take(), self.max_output);
+
+        let deadline = Instant::now() + limit;
+        let finished = timeout_at(deadline, async {
+            let status = child.wait().await;
+            // Background jobs can hold the pipes open after the checker exits
+            let stdout = (&mut stdout).await.unwrap_or_default();
+            let stderr = (&mut stderr).await.unwrap_or_default();
+            (status, stdout, stderr)
+        })
+        .await;
+
+        let output = match finished {
+            Ok((status, stdout, stderr)) => ProcessOutput {
+                status: status.ok(),
+                stdout,
+                stderr,
+                timed_out: false,
+            },
+            Err(_) => {
+                if let Some(pid) = pid {
+                    kill_group(pid);
+                }
+                self.killed.fetch_add(1, Ordering::Relaxed);
+                let status = timeout(KILL_GRACE, child.wait()).await;
+                let stdout = collect(stdout).await;
+                let stderr = collect(stderr).await;
+                ProcessOutput {
+                    status: status
+                        .ok()
+                        .and_then(|s| s.ok())
+                        .filter(|s| s.code().is_some()),
+                    stdout,
+                    stderr,
+                    timed_out: true,
+                }
+            }
+        };
+        self.running.fetch_sub(1, Ordering::Relaxed);
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        Ok(output)
+    }
+}
+
+fn kill_group(pid: u32) {
+    debug!("Killing process group {}", pid);
+    // SAFETY: killpg has no memory safety requirements. The group was
+    // created for this child with process_group(0) so its id is the pid.
+    let res = unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) };
+    if res != 0 {
+        warn!(
+            "Failed to kill process group {}: {}",
+            pid,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// Reads everything from a pipe but only keeps the first `max` bytes, so a
+/// chatty checker can't block on a full pipe or fill up memory.
+fn read_capped<R>(reader: Option<R>, max: usize) -> JoinHandle<Vec<u8>>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut kept = Vec::new();
+        let Some(mut reader) = reader else {
+            return kept;
+        };
+        let mut buf = [0u8; 8192];
+        loop {
+            match reader.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let room = max.saturating_sub(kept.len());
+                    kept.extend_from_slice(&buf[..n.min(room)]);
+                }
+            }
+        }
+        kept
+    })
+}
+
+/// Output of a killed process, giving up if the pipes stay open.
+async fn collect(mut handle: JoinHandle<Vec<u8>>) -> Vec<u8> {
+    match timeout(KILL_GRACE, &mut handle).await {
+        Ok(output) => output.unwrap_or_default(),
+        Err(_) => {
+            handle.abort();
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn bash(script: &str) -> Command {
+        let mut command = Command::new("bash");
+        command.arg("-c").arg(script);
+        command
+    }
+    #[tokio::test]
+    async fn kills_whole_group_on_timeout() {
+        let executor = Executor::new(4, 2, 1024);
+        let start = std::time::Instant::now();
+        // The background sleep keeps stdout open after bash is killed
+        let output = executor
+            .run(
+                Some("host"),
+                bash("sleep 30 & echo started; sleep 30"),
+                Duration::from_millis(300),
+            )
+            .await
+            .unwrap();
+        assert!(output.timed_out);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(output.stdout, b"started\n");
+        let stats = executor.stats();
+        assert_eq!((stats.killed, stats.running, stats.queued), (1, 0, 0));
+    }
+    #[tokio::test]
+    async fn caps_output() {
+        let executor = Executor::new(4, 2, 1024);
+        let output = executor
+            .run(
+                None,
+                bash("head -c 100000 /dev/zero; exit 2"),
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+        assert!(!output.timed_out);
+        assert_eq!(output.stdout.len(), 1024);
+        assert_eq!(output.status.and_then(|s| s.code()), Some(2));
+    }
+    #[tokio::test]
+    async fn busy_host_leaves_slots_for_others() {
+        let executor = Executor::new(2, 1, 1024);
+        let slow = || tokio::time::sleep(Duration::from_millis(500));
+        let busy = async {
+            tokio::join!(
+                executor.run_native(Some("busy"), slow(), Duration::from_secs(5)),
+                executor.run_native(Some("busy"), slow(), Duration::from_secs(5)),
+            )
+        };
+        let other = async {
+            // Let both checks on the busy host queue up first
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let start = std::time::Instant::now();
+            executor.run_native(Some("other"), async {}, Duration::from_secs(5)).await;
+            start.elapsed()
+        };
+        let (_, waited) = tokio::join!(busy, other);
+        assert!(waited < Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::service::CheckStatus;
+
+/// The result of a single scheduled check of one service for one team.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CheckRecord {
+    pub team: String,
+    pub service: String,
+    pub tick: u64,
+    /// Unix time in milliseconds when the check finished
+    pub time: u128,
+    pub up: bool,
+    pub status: CheckStatus,
+    /// Share of the service's points earned
+    pub fraction: f64,
+    /// Message from the checker's structured report
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metrics: BTreeMap<String, f64>,
+    /// How long the check took in milliseconds
+    pub duration: u64,
+    /// None if the checker timed out or could not be run
+    pub exit_code: Option<i32>,
+    /// Usernames the checker was given, by password group
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub logins: BTreeMap<String, String>,
+}
+
+/// Every check result of the game, oldest first.
+///
+/// The log is shared between clones of the config so the copy used for a
+/// score tick writes straight into the live config without copying the whole
+/// history every tick.
+#[derive(Clone, Default)]
+pub struct CheckHistory {
+    records: Arc<RwLock<Vec<CheckRecord>>>,
+}
+
+impl CheckHistory {
+    pub fn push(&self, record: CheckRecord) {
+        if let Ok(mut records) = self.records.write() {
+            records.push(record);
+        }
+    }
+    pub fn query(&self, query: &HistoryQuery) -> HistoryPage {
+        let per_page = query.per_page.clamp(1, MAX_PER_PAGE);
+        let Ok(records) = self.records.read() else {
+            return HistoryPage::empty(query.page, per_page);
+        };
+        let matching = records.iter().filter(|r| query.matches(r));
+        let total = matching.clone().count();
+        let records = matching
+            .skip(query.page * per_page)
+            .take(per_page)
+            .cloned()
+            .collect();
+        HistoryPage {
+            records,
+            total,
+            page: query.page,
+            per_page,
+        }
+    }
+}
+
+impl Serialize for CheckHistory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.records.read() {
+            Ok(records) => records.serialize(serializer),
+            Err(_) => Vec::<CheckRecord>::new().serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CheckHistory {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let records = Vec::<CheckRecord>::deserialize(deserializer)?;
+        Ok(CheckHistory {
+            records: Arc::new(RwLock::new(records)),
+        })
+    }
+}
+
+const MAX_PER_PAGE: usize = 1000;
+
+fn default_per_page() -> usize {
+    100
+}
+
+/// Filters for reading the check history. Times are unix milliseconds and
+/// both ends of the range are inclusive.
+#[derive(Deserialize, Debug)]
+pub struct HistoryQuery {
+    pub team: Option<String>,
+    pub service: Option<String>,
+    pub from: Option<u128>,
+    pub to: Option<u128>,
+    #[serde(default)]
+    pub page: usize,
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+}
+
+impl HistoryQuery {
+    fn matches(&self, record: &CheckRecord) -> bool {
+        self.team.as_ref().is_none_or(|t| t == &record.team)
+            && self.service.as_ref().is_none_or(|s| s == &record.service)
+            && self.from.is_none_or(|from| record.time >= from)
+            && self.to.is_none_or(|to| record.time <= to)
+    }
+}
+
+#[derive(Serialize)]
+pub struct HistoryPage {
+    pub records: Vec<CheckRecord>,
+    /// Number of records matching the query across all pages
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+}
+
+impl HistoryPage {
+    fn empty(page: usize, per_page: usize) -> Self {
+        HistoryPage {
+            records: vec![],
+            total: 0,
+            page,
+            per_page,
+        }
+    }
+}
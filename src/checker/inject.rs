@@ -108,14 +108,14 @@ impl Inject {
             .as_millis();
         Ok(InjectResponse {
             uuid: Uuid::new_v4(),
-            inject_uuid: self.uuid.clone(),
+            inject_uuid: self.uuid,
             late: self.completed,
             filename: filename.to_string(),
             upload_time: time,
             name: self.name.clone(),
         })
     }
-    pub fn get_html(&self, env: &Vec<(String, String)>) -> String {
+    pub fn get_html(&self, env: &[(String, String)]) -> String {
         let map: BTreeMap<String, String> = env.iter().cloned().collect();
         let reg = Handlebars::new();
         let filled_md = reg
@@ -138,7 +138,7 @@ pub fn load_injects() -> Vec<Inject> {
         return Vec::new();
     };
     let yaml_tree: BTreeMap<String, YAMLInject> =
-        serde_yaml::from_str(&file).expect(format!("{} is not valid", inject_file).as_str());
+        serde_yaml::from_str(&file).unwrap_or_else(|_| panic!("{} is not valid", inject_file));
     let injects: Vec<Inject> = yaml_tree
         .into_iter()
         .map(|(name, inject)| Inject::from_yaml(name, inject))
@@ -149,6 +149,7 @@ pub fn load_injects() -> Vec<Inject> {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum SideEffect {
     DeleteService(String),
     AddService(Service),
@@ -317,7 +318,7 @@ mod tests {
             file_type: None,
             sticky: false,
         };
-        let vec = &vec![("VARIABLE".to_string(), "test".to_string())];
+        let vec = &[("VARIABLE".to_string(), "test".to_string())];
         let html = inject.get_html(vec);
         assert_eq!(html, "<p>This is a test inject test</p>\n");
     }
@@ -332,18 +333,14 @@ mod tests {
             side_effects: vec![
                 SideEffect::EditService(
                     "test".to_string(),
-                    Service {
-                        name: "test".to_string(),
-                        command: "test".to_string(),
-                        multiplier: 1,
-                    },
+                    Service::new("test".to_string(), "test".to_string(), 1.0),
                 ),
                 SideEffect::DeleteService("test".to_string()),
-                SideEffect::AddService(Service {
-                    name: "test".to_string(),
-                    command: "test".to_string(),
-                    multiplier: 1,
-                }),
+                SideEffect::AddService(Service::new(
+                    "test".to_string(),
+                    "test".to_string(),
+                    1.0,
+                )),
             ],
             completed: false,
             file_type: None,
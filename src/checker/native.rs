@@ -0,0 +1,263 @@
+mod database;
+mod dns;
+mod ftp;
+mod http;
+mod integrity;
+mod mail;
+mod mysql;
+mod script;
+mod tls;
+
+use std::{
+    error::Error,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+use super::password::{pick_password, PasswordError, UserPass};
+
+use self::{database::Database, script::Transport};
+pub use self::{
+    database::DatabaseCheck,
+    dns::DnsCheck,
+    ftp::FtpCheck,
+    http::HttpCheck,
+    integrity::{Baseline, ContentSource, IntegrityCheck},
+    mail::{MailboxCheck, SmtpCheck},
+    script::ScriptCheck,
+    tls::TlsCheck,
+};
+
+/// A check the scoreboard runs itself instead of calling a checker script.
+/// Written in services.yaml as a map with the kind of check as its only key.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NativeCheck {
+    Http(HttpCheck),
+    Dns(DnsCheck),
+    Smtp(SmtpCheck),
+    Pop3(MailboxCheck),
+    Imap(MailboxCheck),
+    Tcp(ScriptCheck),
+    Udp(ScriptCheck),
+    Mysql(DatabaseCheck),
+    Postgres(DatabaseCheck),
+    Ftp(FtpCheck),
+    Tls(TlsCheck),
+    /// Compared against the team's baseline by the service
+    Integrity(IntegrityCheck),
+}
+
+/// A short description of what was checked if the service is up, or why it
+/// is down.
+pub type CheckResult = Result<String, String>;
+
+impl NativeCheck {
+    pub fn is_valid(&self) -> bool {
+        match self {
+            NativeCheck::Http(check) => check.is_valid(),
+            NativeCheck::Dns(check) => check.is_valid(),
+            NativeCheck::Smtp(check) => check.is_valid(),
+            NativeCheck::Pop3(check) | NativeCheck::Imap(check) => check.is_valid(),
+            NativeCheck::Tcp(check) | NativeCheck::Udp(check) => check.is_valid(),
+            NativeCheck::Mysql(check) | NativeCheck::Postgres(check) => check.is_valid(),
+            NativeCheck::Ftp(check) => check.is_valid(),
+            NativeCheck::Tls(check) => check.is_valid(),
+            NativeCheck::Integrity(check) => check.is_valid(),
+        }
+    }
+    /// Whether the check can log in with a login picked by its service
+    pub fn logs_in(&self) -> bool {
+        matches!(
+            self,
+            NativeCheck::Smtp(_)
+                | NativeCheck::Pop3(_)
+                | NativeCheck::Imap(_)
+                | NativeCheck::Mysql(_)
+                | NativeCheck::Postgres(_)
+                | NativeCheck::Ftp(_)
+        )
+    }
+    /// Whether the check can't run without a login
+    pub fn needs_login(&self) -> bool {
+        matches!(self, NativeCheck::Mysql(_) | NativeCheck::Postgres(_))
+    }
+    /// The password group and user the check names itself, if it does
+    pub fn own_credentials(&self) -> Option<(&String, Option<&str>)> {
+        let (credentials, user) = match self {
+            NativeCheck::Smtp(check) => (&check.credentials, &check.user),
+            NativeCheck::Pop3(check) | NativeCheck::Imap(check) => {
+                (&check.credentials, &check.user)
+            }
+            NativeCheck::Mysql(check) | NativeCheck::Postgres(check) => {
+                (&check.credentials, &check.user)
+            }
+            NativeCheck::Ftp(check) => (&check.credentials, &check.user),
+            _ => return None,
+        };
+        credentials.as_ref().map(|group| (group, user.as_deref()))
+    }
+    /// Runs the check against a team using their environment variables,
+    /// logging in with `login` if the check logs in. `timeout` is how long
+    /// the whole check may take.
+    pub async fn run(
+        &self,
+        team: &str,
+        env: &[(String, String)],
+        login: Option<&UserPass>,
+        timeout: Duration,
+    ) -> CheckResult {
+        match self {
+            NativeCheck::Http(check) => check.run(env, timeout).await,
+            NativeCheck::Dns(check) => check.run(env).await,
+            NativeCheck::Smtp(check) => check.run(team, env, login, timeout).await,
+            NativeCheck::Pop3(check) => check.run_pop3(env, login).await,
+            NativeCheck::Imap(check) => check.run_imap(env, login).await,
+            NativeCheck::Tcp(check) => check.run(Transport::Tcp, env).await,
+            NativeCheck::Udp(check) => check.run(Transport::Udp, env).await,
+            NativeCheck::Mysql(check) => check.run(Database::Mysql, env, login).await,
+            NativeCheck::Postgres(check) => check.run(Database::Postgres, env, login).await,
+            NativeCheck::Ftp(check) => check.run(env, login).await,
+            NativeCheck::Tls(check) => check.run(env).await,
+            NativeCheck::Integrity(check) => check
+                .fetch(team, env, timeout)
+                .await
+                .map(|content| format!("fetched {} bytes", content.len())),
+        }
+    }
+}
+
+/// A setting that takes either a single value or a list of them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    pub fn as_slice(&self) -> &[T] {
+        match self {
+            OneOrMany::One(value) => std::slice::from_ref(value),
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+/// A login from one of the team's password groups. A random user is
+/// picked unless `user` is set.
+pub fn team_login(team: &str, group: &String, user: Option<&str>) -> Result<UserPass, String> {
+    match pick_password(&team.to_string(), group, user) {
+        Ok(login) => Ok(login),
+        Err(PasswordError::NoSuchUser) => match user {
+            Some(user) => Err(format!("no user {} in password group {}", user, group)),
+            None => Err(format!("password group {} is empty", group)),
+        },
+        Err(_) => Err(format!("{} has no password group {}", team, group)),
+    }
+}
+
+/// Longest line accepted from a server before giving up on it
+const MAX_LINE: u64 = 64 * 1024;
+
+/// A connection to a line based protocol like SMTP or FTP.
+pub struct LineConnection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    pub server: String,
+}
+
+impl LineConnection {
+    pub async fn connect(server: &str) -> Result<Self, String> {
+        let stream = TcpStream::connect(server)
+            .await
+            .map_err(|err| format!("failed to connect to {}: {}", server, err))?;
+        let (reader, writer) = stream.into_split();
+        Ok(LineConnection {
+            reader: BufReader::new(reader),
+            writer,
+            server: server.to_string(),
+        })
+    }
+    /// The next line without its line ending
+    pub async fn read_line(&mut self) -> Result<String, String> {
+        let mut line = Vec::new();
+        (&mut self.reader)
+            .take(MAX_LINE)
+            .read_until(b'\n', &mut line)
+            .await
+            .map_err(|err| format!("failed to read from {}: {}", self.server, err))?;
+        if line.is_empty() {
+            return Err(format!("{} closed the connection", self.server));
+        }
+        let line = String::from_utf8_lossy(&line);
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+    /// Sends a line, adding CRLF
+    pub async fn write_line(&mut self, line: &str) -> Result<(), String> {
+        self.writer
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .await
+            .map_err(|err| format!("failed to write to {}: {}", self.server, err))
+    }
+}
+
+/// Fails if a server's greeting doesn't contain the expected banner.
+pub fn check_banner(greeting: &str, banner: Option<&str>) -> Result<(), String> {
+    match banner {
+        Some(banner) if !greeting.contains(banner) => Err(format!(
+            "banner {:?} does not contain {:?}",
+            greeting, banner
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Adds `port` to a server address that doesn't already have one.
+pub fn with_default_port(server: &str, port: u16) -> String {
+    if server.parse::<SocketAddr>().is_ok() {
+        return server.to_string();
+    }
+    match server.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port).to_string(),
+        Err(_) if server.contains(':') => server.to_string(),
+        Err(_) => format!("{}:{}", server, port),
+    }
+}
+
+/// An error along with everything that caused it, since the top level error
+/// is often too vague to tell what went wrong.
+pub fn describe_error(err: &dyn Error) -> String {
+    let mut description = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        let message = err.to_string();
+        if !description.contains(&message) {
+            description.push_str(": ");
+            description.push_str(&message);
+        }
+        source = err.source();
+    }
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn default_ports() {
+        assert_eq!(with_default_port("10.0.0.1", 53), "10.0.0.1:53");
+        assert_eq!(with_default_port("10.0.0.1:5353", 53), "10.0.0.1:5353");
+        assert_eq!(with_default_port("::1", 53), "[::1]:53");
+        assert_eq!(with_default_port("ns.team1.local", 53), "ns.team1.local:53");
+    }
+}
@@ -0,0 +1,377 @@
+use serde::{Deserialize, Serialize};
+use tokio_postgres::{NoTls, SimpleQueryMessage};
+
+use super::{describe_error, mysql, with_default_port, CheckResult};
+use crate::checker::{env::substitute, password::UserPass};
+
+/// Logs in to a team's MySQL or PostgreSQL server and optionally runs a
+/// query, comparing the rows it returns. Queries run in a read only
+/// transaction that is rolled back afterwards.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DatabaseCheck {
+    /// Server to connect to, with the database's usual port unless one is given
+    pub server: String,
+    /// Password group to log in with. The service's credentials are used
+    /// if not set.
+    #[serde(default)]
+    pub credentials: Option<String>,
+    /// User from the password group. A random one if not set.
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub database: Option<String>,
+    /// Query to run, which can use the team's variables
+    #[serde(default)]
+    pub query: Option<String>,
+    /// Rows the query must return, each a list of column values. NULL is
+    /// written as `~`.
+    #[serde(default)]
+    pub expect: Option<Vec<Row>>,
+    /// Fail if the query returns rows besides the expected ones
+    #[serde(default)]
+    pub exact: bool,
+}
+
+pub type Row = Vec<Option<String>>;
+
+#[derive(Clone, Copy)]
+pub enum Database {
+    Mysql,
+    Postgres,
+}
+
+impl DatabaseCheck {
+    pub fn is_valid(&self) -> bool {
+        !self.server.is_empty()
+            && self.credentials.as_ref().is_none_or(|group| !group.is_empty())
+            && self.query.as_ref().is_none_or(|q| !q.trim().is_empty())
+            && (self.query.is_some() || self.expect.is_none())
+    }
+    pub async fn run(
+        &self,
+        database: Database,
+        env: &[(String, String)],
+        login: Option<&UserPass>,
+    ) -> CheckResult {
+        let port = match database {
+            Database::Mysql => 3306,
+            Database::Postgres => 5432,
+        };
+        let server = with_default_port(&substitute(&self.server, env), port);
+        let login = login.ok_or_else(|| "no login for the database".to_string())?;
+        let session = Session {
+            server: &server,
+            login,
+            database: self.database.as_ref().map(|d| substitute(d, env)),
+            query: self.query.as_ref().map(|q| substitute(q, env)),
+        };
+        let rows = match database {
+            Database::Mysql => session.mysql().await?,
+            Database::Postgres => session.postgres().await?,
+        };
+        let Some(rows) = rows else {
+            return Ok(format!("logged in to {} as {}", server, login.username));
+        };
+        self.compare(&rows, env)?;
+        Ok(format!(
+            "logged in to {} as {}, query returned {} rows",
+            server,
+            login.username,
+            rows.len()
+        ))
+    }
+    /// Checks the rows a query returned against the expected ones.
+    fn compare(&self, rows: &[Row], env: &[(String, String)]) -> Result<(), String> {
+        let Some(expect) = &self.expect else {
+            return Ok(());
+        };
+        let expected: Vec<Row> = expect
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|value| value.as_ref().map(|v| substitute(v, env)))
+                    .collect()
+            })
+            .collect();
+        let missing = expected.iter().any(|row| !rows.contains(row));
+        let extra = rows.iter().any(|row| !expected.contains(row));
+        if missing || (self.exact && extra) {
+            return Err(format!(
+                "query returned {}, expected {}",
+                format_rows(rows),
+                format_rows(&expected)
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Rows for a failure reason, cut short if there are a lot of them.
+fn format_rows(rows: &[Row]) -> String {
+    const MAX: usize = 5;
+    let mut formatted: Vec<String> = rows
+        .iter()
+        .take(MAX)
+        .map(|row| {
+            let values: Vec<&str> = row.iter().map(|v| v.as_deref().unwrap_or("NULL")).collect();
+            format!("({})", values.join(", "))
+        })
+        .collect();
+    if rows.len() > MAX {
+        formatted.push(format!("and {} more", rows.len() - MAX));
+    }
+    if formatted.is_empty() {
+        return "no rows".to_string();
+    }
+    formatted.join(", ")
+}
+
+struct Session<'a> {
+    server: &'a str,
+    login: &'a UserPass,
+    database: Option<String>,
+    query: Option<String>,
+}
+
+impl Session<'_> {
+    async fn mysql(&self) -> Result<Option<Vec<Row>>, String> {
+        let mut conn = mysql::Connection::connect(self.server).await?;
+        conn.login(self.login, self.database.as_deref())
+            .await
+            .map_err(|err| format!("login as {} failed: {}", self.login.username, err))?;
+        let Some(query) = &self.query else {
+            conn.quit().await;
+            return Ok(None);
+        };
+        conn.query("START TRANSACTION READ ONLY").await?;
+        let rows = conn
+            .query(query)
+            .await
+            .map_err(|err| format!("query failed: {}", err))?;
+        conn.query("ROLLBACK").await?;
+        conn.quit().await;
+        Ok(Some(rows))
+    }
+    async fn postgres(&self) -> Result<Option<Vec<Row>>, String> {
+        let (host, port) = self
+            .server
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+            .ok_or_else(|| format!("invalid server {}", self.server))?;
+        let mut config = tokio_postgres::Config::new();
+        config
+            .host(host.trim_start_matches('[').trim_end_matches(']'))
+            .port(port)
+            .user(&self.login.username)
+            .password(&self.login.password);
+        if let Some(database) = &self.database {
+            config.dbname(database);
+        }
+        let (client, connection) = config.connect(NoTls).await.map_err(|err| {
+            format!(
+                "login as {} failed: {}",
+                self.login.username,
+                describe_error(&err)
+            )
+        })?;
+        let connection = tokio::spawn(connection);
+        let result = match &self.query {
+            Some(query) => postgres_query(&client, query).await.map(Some),
+            None => Ok(None),
+        };
+        drop(client);
+        let _ = connection.await;
+        result
+    }
+}
+
+async fn postgres_query(client: &tokio_postgres::Client, query: &str) -> Result<Vec<Row>, String> {
+    client
+        .batch_execute("START TRANSACTION READ ONLY")
+        .await
+        .map_err(|err| describe_error(&err))?;
+    let messages = client
+        .simple_query(query)
+        .await
+        .map_err(|err| format!("query failed: {}", describe_error(&err)))?;
+    client
+        .batch_execute("ROLLBACK")
+        .await
+        .map_err(|err| describe_error(&err))?;
+    Ok(messages
+        .iter()
+        .filter_map(|message| match message {
+            SimpleQueryMessage::Row(row) => Some(
+                (0..row.len())
+                    .map(|i| row.get(i).map(str::to_string))
+                    .collect(),
+            ),
+            _ => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    async fn read_message(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+        let tag = stream.read_u8().await.ok()?;
+        let len = stream.read_u32().await.ok()?;
+        let mut body = vec![0; len as usize - 4];
+        stream.read_exact(&mut body).await.ok()?;
+        Some((tag, body))
+    }
+
+    fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![tag];
+        message.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+        message.extend_from_slice(body);
+        message
+    }
+
+    fn text(values: &[&str]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| [v.as_bytes(), b"\0"].concat())
+            .collect()
+    }
+
+    /// Just enough of PostgreSQL to log in with a cleartext password and
+    /// answer `SELECT name` with two rows.
+    async fn serve_postgres() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let len = stream.read_u32().await.unwrap();
+                let mut startup = vec![0; len as usize - 4];
+                stream.read_exact(&mut startup).await.unwrap();
+                stream
+                    .write_all(&message(b'R', &3u32.to_be_bytes()))
+                    .await
+                    .unwrap();
+                let (_, password) = read_message(&mut stream).await.unwrap();
+                if password != b"hunter2\0" {
+                    let error = [text(&["SFATAL", "C28P01", "Mbad password"]), vec![0]].concat();
+                    stream.write_all(&message(b'E', &error)).await.unwrap();
+                    continue;
+                }
+                stream
+                    .write_all(&message(b'R', &0u32.to_be_bytes()))
+                    .await
+                    .unwrap();
+                stream.write_all(&message(b'Z', b"I")).await.unwrap();
+                while let Some((b'Q', query)) = read_message(&mut stream).await {
+                    let mut reply = Vec::new();
+                    if query.starts_with(b"SELECT name") {
+                        let mut columns = 1u16.to_be_bytes().to_vec();
+                        columns.extend_from_slice(b"name\0");
+                        columns.extend_from_slice(&[0; 6]);
+                        columns.extend_from_slice(&25u32.to_be_bytes());
+                        columns.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0]);
+                        reply.extend(message(b'T', &columns));
+                        for value in ["widget", "gadget"] {
+                            let mut row = 1u16.to_be_bytes().to_vec();
+                            row.extend_from_slice(&(value.len() as u32).to_be_bytes());
+                            row.extend_from_slice(value.as_bytes());
+                            reply.extend(message(b'D', &row));
+                        }
+                        reply.extend(message(b'C', b"SELECT 2\0"));
+                    } else {
+                        reply.extend(message(b'C', &text(&["OK"])));
+                    }
+                    reply.extend(message(b'Z', b"T"));
+                    stream.write_all(&reply).await.unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    fn check(yaml: &str) -> DatabaseCheck {
+        let check: DatabaseCheck = serde_yaml::from_str(yaml).unwrap();
+        assert!(check.is_valid());
+        check
+    }
+
+    #[tokio::test]
+    async fn postgres_queries() {
+        let server = serve_postgres().await;
+        let session = |password: &'static str, query: Option<&'static str>| {
+            let server = server.clone();
+            async move {
+                let login = UserPass {
+                    username: "app".to_string(),
+                    password: password.to_string(),
+                };
+                Session {
+                    server: &server,
+                    login: &login,
+                    database: None,
+                    query: query.map(str::to_string),
+                }
+                .postgres()
+                .await
+            }
+        };
+        assert_eq!(session("hunter2", None).await, Ok(None));
+        assert_eq!(
+            session("hunter2", Some("SELECT name FROM products")).await,
+            Ok(Some(vec![
+                vec![Some("widget".to_string())],
+                vec![Some("gadget".to_string())]
+            ]))
+        );
+        let failed = session("wrong", None).await.unwrap_err();
+        assert!(failed.starts_with("login as app failed: "), "{}", failed);
+        assert!(failed.contains("bad password"), "{}", failed);
+
+        let check = check("{server: $DB, credentials: db, query: '$QUERY', expect: [[widget]]}");
+        let env = vec![
+            ("DB".to_string(), server.clone()),
+            ("QUERY".to_string(), "SELECT name FROM products".to_string()),
+        ];
+        let login = UserPass {
+            username: "app".to_string(),
+            password: "hunter2".to_string(),
+        };
+        let result = check.run(Database::Postgres, &env, Some(&login)).await;
+        assert!(result.unwrap().ends_with("query returned 2 rows"));
+    }
+
+    #[test]
+    fn compare_rows() {
+        assert!(!serde_yaml::from_str::<DatabaseCheck>(
+            "{server: db, credentials: db, expect: [[a]]}"
+        )
+        .unwrap()
+        .is_valid());
+        let env = vec![("TEAM".to_string(), "team1".to_string())];
+        let row = |values: &[Option<&str>]| -> Row {
+            values.iter().map(|v| v.map(str::to_string)).collect()
+        };
+        let rows = vec![row(&[Some("team1"), None]), row(&[Some("b"), Some("1")])];
+        let check = check("{server: db, credentials: db, query: SELECT 1, expect: [[$TEAM, ~]]}");
+        assert_eq!(check.compare(&rows, &env), Ok(()));
+        let exact = DatabaseCheck {
+            exact: true,
+            ..check.clone()
+        };
+        assert_eq!(
+            exact.compare(&rows, &env),
+            Err("query returned (team1, NULL), (b, 1), expected (team1, NULL)".to_string())
+        );
+        assert!(check.compare(&rows[1..], &env).is_err());
+        assert_eq!(
+            format_rows(&[vec![Some("a".to_string()), None]]),
+            "(a, NULL)"
+        );
+        assert_eq!(format_rows(&[]), "no rows");
+    }
+}
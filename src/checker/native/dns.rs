@@ -0,0 +1,359 @@
+use std::{fmt, net::IpAddr};
+
+use hickory_proto::{
+    op::{Message, MessageType, OpCode, Query, ResponseCode},
+    rr::{Name, RecordType},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+
+use super::{with_default_port, CheckResult, OneOrMany};
+use crate::checker::env::substitute;
+
+/// Asks a team's DNS server for a record and compares the answers. The
+/// server and name can use the team's variables like `$DNS_SERVER`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DnsCheck {
+    /// Server to ask, with port 53 unless one is given
+    pub server: String,
+    /// Name to look up. For PTR records this can be an IP address.
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub record_type: DnsRecordType,
+    /// Answers that must come back, written like they are in a zone file.
+    /// Any answer will do if not set.
+    #[serde(default)]
+    pub expect: Option<OneOrMany<String>>,
+    /// Fail if anything besides the expected answers comes back
+    #[serde(default)]
+    pub exact: bool,
+    /// Query over TCP instead of UDP. UDP queries switch to TCP when the
+    /// answer is truncated.
+    #[serde(default)]
+    pub tcp: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DnsRecordType {
+    #[default]
+    A,
+    Aaaa,
+    Mx,
+    Srv,
+    Txt,
+    Ptr,
+}
+
+impl From<DnsRecordType> for RecordType {
+    fn from(record_type: DnsRecordType) -> Self {
+        match record_type {
+            DnsRecordType::A => RecordType::A,
+            DnsRecordType::Aaaa => RecordType::AAAA,
+            DnsRecordType::Mx => RecordType::MX,
+            DnsRecordType::Srv => RecordType::SRV,
+            DnsRecordType::Txt => RecordType::TXT,
+            DnsRecordType::Ptr => RecordType::PTR,
+        }
+    }
+}
+
+impl fmt::Display for DnsRecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        RecordType::from(*self).fmt(f)
+    }
+}
+
+impl DnsCheck {
+    pub fn is_valid(&self) -> bool {
+        !self.server.is_empty()
+            && !self.name.is_empty()
+            && self
+                .expect
+                .as_ref()
+                .is_none_or(|e| !e.as_slice().is_empty())
+    }
+    pub async fn run(&self, env: &[(String, String)]) -> CheckResult {
+        let server = with_default_port(&substitute(&self.server, env), 53);
+        let name = substitute(&self.name, env);
+        let mut query_name = match (self.record_type, name.parse::<IpAddr>()) {
+            (DnsRecordType::Ptr, Ok(ip)) => Name::from(ip),
+            _ => {
+                Name::from_ascii(&name).map_err(|err| format!("invalid name {}: {}", name, err))?
+            }
+        };
+        query_name.set_fqdn(true);
+        let record_type = RecordType::from(self.record_type);
+
+        let mut query = Message::new();
+        query
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(query_name, record_type));
+        let query = query.to_vec().map_err(|err| err.to_string())?;
+
+        let mut response = if self.tcp {
+            query_tcp(&server, &query).await?
+        } else {
+            query_udp(&server, &query).await?
+        };
+        if response.truncated() {
+            response = query_tcp(&server, &query).await?;
+        }
+        if response.response_code() != ResponseCode::NoError {
+            return Err(format!(
+                "{} {} from {}: {}",
+                name,
+                self.record_type,
+                server,
+                response.response_code()
+            ));
+        }
+
+        let answers: Vec<String> = response
+            .answers()
+            .iter()
+            .filter(|record| record.record_type() == record_type)
+            .filter_map(|record| record.data())
+            .map(|data| normalize(self.record_type, &data.to_string()))
+            .collect();
+        if answers.is_empty() {
+            return Err(format!("no {} records for {}", self.record_type, name));
+        }
+        if let Some(expect) = &self.expect {
+            let expected: Vec<String> = expect
+                .as_slice()
+                .iter()
+                .map(|e| normalize(self.record_type, &substitute(e, env)))
+                .collect();
+            let missing: Vec<&String> = expected.iter().filter(|e| !answers.contains(e)).collect();
+            let extra: Vec<&String> = answers.iter().filter(|a| !expected.contains(a)).collect();
+            if !missing.is_empty() || (self.exact && !extra.is_empty()) {
+                return Err(format!(
+                    "{} {} returned {}, expected {}",
+                    name,
+                    self.record_type,
+                    answers.join(", "),
+                    expected.join(", ")
+                ));
+            }
+        }
+        Ok(format!(
+            "{} {} {}",
+            name,
+            self.record_type,
+            answers.join(", ")
+        ))
+    }
+}
+
+/// Puts an answer in a form that can be compared, ignoring case and
+/// trailing dots on names.
+fn normalize(record_type: DnsRecordType, answer: &str) -> String {
+    let answer = answer.trim();
+    match record_type {
+        DnsRecordType::Txt => answer.to_string(),
+        DnsRecordType::A | DnsRecordType::Aaaa => match answer.parse::<IpAddr>() {
+            Ok(ip) => ip.to_string(),
+            Err(_) => answer.to_string(),
+        },
+        _ => answer
+            .split_whitespace()
+            .map(|part| part.trim_end_matches('.').to_lowercase())
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+fn parse_response(response: &[u8], id: u16) -> Result<Option<Message>, String> {
+    let message =
+        Message::from_vec(response).map_err(|err| format!("invalid DNS response: {}", err))?;
+    Ok((message.id() == id && message.message_type() == MessageType::Response).then_some(message))
+}
+
+fn query_id(query: &[u8]) -> u16 {
+    u16::from_be_bytes([query[0], query[1]])
+}
+
+async fn query_udp(server: &str, query: &[u8]) -> Result<Message, String> {
+    let socket = UdpSocket::bind(if server.starts_with('[') {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    })
+    .await
+    .map_err(|err| format!("failed to open socket: {}", err))?;
+    socket
+        .connect(server)
+        .await
+        .map_err(|err| format!("failed to reach {}: {}", server, err))?;
+    socket
+        .send(query)
+        .await
+        .map_err(|err| format!("failed to query {}: {}", server, err))?;
+    let mut buf = vec![0; 4096];
+    loop {
+        let len = socket
+            .recv(&mut buf)
+            .await
+            .map_err(|err| format!("no answer from {}: {}", server, err))?;
+        // Ignore stray packets that aren't the answer to this query
+        if let Ok(Some(message)) = parse_response(&buf[..len], query_id(query)) {
+            return Ok(message);
+        }
+    }
+}
+
+async fn query_tcp(server: &str, query: &[u8]) -> Result<Message, String> {
+    let mut stream = TcpStream::connect(server)
+        .await
+        .map_err(|err| format!("failed to connect to {}: {}", server, err))?;
+    let mut request = (query.len() as u16).to_be_bytes().to_vec();
+    request.extend_from_slice(query);
+    stream
+        .write_all(&request)
+        .await
+        .map_err(|err| format!("failed to query {}: {}", server, err))?;
+    let len = stream
+        .read_u16()
+        .await
+        .map_err(|err| format!("no answer from {}: {}", server, err))?;
+    let mut response = vec![0; len as usize];
+    stream
+        .read_exact(&mut response)
+        .await
+        .map_err(|err| format!("incomplete answer from {}: {}", server, err))?;
+    parse_response(&response, query_id(query))?
+        .ok_or_else(|| format!("{} answered a different query", server))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use hickory_proto::rr::{
+        rdata::{A, MX, PTR, TXT},
+        RData, Record,
+    };
+
+    use super::*;
+
+    /// Answers queries for a tiny zone. Over UDP the TXT answer is marked
+    /// truncated to make the client retry over TCP.
+    fn answer(query: &[u8], udp: bool) -> Vec<u8> {
+        let query = Message::from_vec(query).unwrap();
+        let question = query.queries()[0].clone();
+        let name = question.name().clone();
+        let mut response = Message::new();
+        response
+            .set_id(query.id())
+            .set_message_type(MessageType::Response)
+            .add_query(question.clone());
+        let rdata = match (name.to_ascii().as_str(), question.query_type()) {
+            ("www.team1.test.", RecordType::A) => vec![
+                RData::A(A(Ipv4Addr::new(10, 0, 1, 5))),
+                RData::A(A(Ipv4Addr::new(10, 0, 1, 6))),
+            ],
+            ("team1.test.", RecordType::MX) => {
+                vec![RData::MX(MX::new(
+                    10,
+                    Name::from_ascii("Mail.team1.test.").unwrap(),
+                ))]
+            }
+            ("team1.test.", RecordType::TXT) if udp => {
+                response.set_truncated(true);
+                vec![]
+            }
+            ("team1.test.", RecordType::TXT) => {
+                vec![RData::TXT(TXT::new(vec!["v=spf1 -all".into()]))]
+            }
+            ("5.1.0.10.in-addr.arpa.", RecordType::PTR) => {
+                vec![RData::PTR(
+                    PTR(Name::from_ascii("www.team1.test.").unwrap()),
+                )]
+            }
+            _ => {
+                response.set_response_code(ResponseCode::NXDomain);
+                vec![]
+            }
+        };
+        for data in rdata {
+            response.add_answer(Record::from_rdata(name.clone(), 60, data));
+        }
+        response.to_vec().unwrap()
+    }
+
+    async fn serve() -> SocketAddr {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = tokio::net::TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            while let Ok((len, from)) = udp.recv_from(&mut buf).await {
+                let _ = udp.send_to(&answer(&buf[..len], true), from).await;
+            }
+        });
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = tcp.accept().await {
+                let len = stream.read_u16().await.unwrap();
+                let mut query = vec![0; len as usize];
+                stream.read_exact(&mut query).await.unwrap();
+                let response = answer(&query, false);
+                stream.write_u16(response.len() as u16).await.unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn check_local_server() {
+        let env = vec![
+            ("DNS".to_string(), serve().await.to_string()),
+            ("TEAM".to_string(), "1".to_string()),
+        ];
+        let run = |yaml: &str| {
+            let check: DnsCheck = serde_yaml::from_str(yaml).unwrap();
+            assert!(check.is_valid());
+            let env = env.clone();
+            async move { check.run(&env).await }
+        };
+
+        assert!(
+            run("{server: $DNS, name: www.team$TEAM.test, expect: 10.0.1.5}")
+                .await
+                .is_ok()
+        );
+        assert!(run(
+            "{server: $DNS, name: team1.test, type: MX, expect: '10 mail.team1.test.', tcp: true}"
+        )
+        .await
+        .is_ok());
+        assert!(
+            run("{server: $DNS, name: team1.test, type: TXT, expect: 'v=spf1 -all'}")
+                .await
+                .is_ok()
+        );
+        assert!(
+            run("{server: $DNS, name: 10.0.1.5, type: PTR, expect: www.team1.test}")
+                .await
+                .is_ok()
+        );
+        assert_eq!(
+            run("{server: $DNS, name: www.team1.test, expect: [10.0.1.5], exact: true}").await,
+            Err("www.team1.test A returned 10.0.1.5, 10.0.1.6, expected 10.0.1.5".to_string())
+        );
+        assert_eq!(
+            run("{server: $DNS, name: www.team1.test, type: AAAA}").await,
+            Err(format!(
+                "www.team1.test AAAA from {}: Non-Existent Domain",
+                env[0].1
+            ))
+        );
+    }
+}
@@ -0,0 +1,361 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{io::AsyncReadExt, net::TcpStream};
+
+use super::{check_banner, with_default_port, CheckResult, LineConnection, OneOrMany};
+use crate::checker::{env::substitute, password::UserPass};
+
+/// Logs in to a team's FTP server and lists a directory or downloads a
+/// file over a passive mode connection.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FtpCheck {
+    /// Server to connect to, with port 21 unless one is given
+    pub server: String,
+    #[serde(default)]
+    pub banner: Option<String>,
+    /// Password group to log in with. Logs in anonymously if not set.
+    #[serde(default)]
+    pub credentials: Option<String>,
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Directory to list
+    #[serde(default)]
+    pub list: Option<String>,
+    /// Names that must show up in the listing
+    #[serde(default)]
+    pub contains: Option<OneOrMany<String>>,
+    /// File to download
+    #[serde(default)]
+    pub retrieve: Option<String>,
+    /// Hex SHA-256 hash the downloaded file must have
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+/// Largest file downloaded before giving up on it
+const MAX_FILE: u64 = 64 * 1024 * 1024;
+
+impl FtpCheck {
+    pub fn is_valid(&self) -> bool {
+        !self.server.is_empty()
+            && (self.list.is_some() || self.contains.is_none())
+            && (self.retrieve.is_some() || self.sha256.is_none())
+            && self
+                .sha256
+                .as_ref()
+                .is_none_or(|hash| hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+    }
+    pub async fn run(&self, env: &[(String, String)], login: Option<&UserPass>) -> CheckResult {
+        self.fetch(env, login).await.map(|(message, _)| message)
+    }
+    /// Runs the check, returning a description of what was done along with
+    /// the downloaded file if there is one. Logs in anonymously without a
+    /// login.
+    pub async fn fetch(
+        &self,
+        env: &[(String, String)],
+        login: Option<&UserPass>,
+    ) -> Result<(String, Option<Vec<u8>>), String> {
+        let server = with_default_port(&substitute(&self.server, env), 21);
+        let banner = self.banner.as_ref().map(|b| substitute(b, env));
+        let login = login.cloned().unwrap_or(UserPass {
+            username: "anonymous".to_string(),
+            password: "scoreboard@".to_string(),
+        });
+        let mut conn = LineConnection::connect(&server).await?;
+        let (code, greeting) = ftp_reply(&mut conn).await?;
+        if code != 220 {
+            return Err(format!("{} greeted with {} {}", server, code, greeting));
+        }
+        check_banner(&greeting, banner.as_deref())?;
+        ftp_login(&mut conn, &login)
+            .await
+            .map_err(|err| format!("login as {} failed: {}", login.username, err))?;
+        let mut done = vec![format!("logged in to {} as {}", server, login.username)];
+
+        if let Some(dir) = &self.list {
+            let dir = substitute(dir, env);
+            let listing = transfer(&mut conn, &format!("NLST {}", dir), false).await?;
+            let listing = String::from_utf8_lossy(&listing);
+            // Some servers list names with the directory in front
+            let names: Vec<&str> = listing
+                .lines()
+                .map(|name| name.trim())
+                .filter(|name| !name.is_empty())
+                .map(|name| name.rsplit('/').next().unwrap_or(name))
+                .collect();
+            for name in self.contains.iter().flat_map(|c| c.as_slice()) {
+                let name = substitute(name, env);
+                if !names.contains(&name.as_str()) {
+                    return Err(format!("{} does not contain {}", dir, name));
+                }
+            }
+            done.push(format!("listed {} files in {}", names.len(), dir));
+        }
+
+        let mut retrieved = None;
+        if let Some(path) = &self.retrieve {
+            let path = substitute(path, env);
+            let file = transfer(&mut conn, &format!("RETR {}", path), true).await?;
+            if let Some(expected) = &self.sha256 {
+                let hash = format!("{:x}", Sha256::digest(&file));
+                if !hash.eq_ignore_ascii_case(expected) {
+                    return Err(format!(
+                        "{} has sha256 {}, expected {}",
+                        path,
+                        hash,
+                        expected.to_lowercase()
+                    ));
+                }
+            }
+            done.push(format!("retrieved {} ({} bytes)", path, file.len()));
+            retrieved = Some(file);
+        }
+        let _ = ftp_command(&mut conn, "QUIT", "QUIT", &[221]).await;
+        Ok((done.join(", "), retrieved))
+    }
+}
+
+/// Reads a possibly multi-line FTP reply. The lines of a multi-line reply
+/// between the first and last don't need to start with the code.
+async fn ftp_reply(conn: &mut LineConnection) -> Result<(u16, String), String> {
+    let line = conn.read_line().await?;
+    let code: u16 = line
+        .get(..3)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| format!("{} sent an invalid reply {:?}", conn.server, line))?;
+    let mut text = vec![line.get(4..).unwrap_or("").to_string()];
+    if line.as_bytes().get(3) == Some(&b'-') {
+        let end = format!("{} ", code);
+        loop {
+            let line = conn.read_line().await?;
+            if let Some(last) = line.strip_prefix(&end) {
+                text.push(last.to_string());
+                break;
+            }
+            text.push(line);
+        }
+    }
+    Ok((code, text.join(" ")))
+}
+
+/// Sends a command and fails unless the reply has one of the expected
+/// codes. `name` is used in errors in place of the command so passwords
+/// aren't shown.
+async fn ftp_command(
+    conn: &mut LineConnection,
+    command: &str,
+    name: &str,
+    expected: &[u16],
+) -> Result<(u16, String), String> {
+    conn.write_line(command).await?;
+    let (code, text) = ftp_reply(conn).await?;
+    if !expected.contains(&code) {
+        return Err(format!(
+            "{} replied to {} with {} {}",
+            conn.server, name, code, text
+        ));
+    }
+    Ok((code, text))
+}
+
+async fn ftp_login(conn: &mut LineConnection, login: &UserPass) -> Result<(), String> {
+    let user = format!("USER {}", login.username);
+    let (code, _) = ftp_command(conn, &user, "USER", &[230, 331]).await?;
+    if code == 331 {
+        let pass = format!("PASS {}", login.password);
+        ftp_command(conn, &pass, "PASS", &[230, 202]).await?;
+    }
+    Ok(())
+}
+
+/// Runs a command that sends data back over a passive mode connection and
+/// returns the data.
+async fn transfer(
+    conn: &mut LineConnection,
+    command: &str,
+    binary: bool,
+) -> Result<Vec<u8>, String> {
+    let kind = if binary { "TYPE I" } else { "TYPE A" };
+    ftp_command(conn, kind, kind, &[200]).await?;
+    let (_, text) = ftp_command(conn, "PASV", "PASV", &[227]).await?;
+    let port = parse_pasv(&text)
+        .ok_or_else(|| format!("{} sent an invalid PASV reply {:?}", conn.server, text))?;
+    // The address in the reply is often wrong behind NAT, so only the port
+    // is used like most clients do
+    let host = conn
+        .server
+        .rsplit_once(':')
+        .map_or(conn.server.as_str(), |(host, _)| host);
+    let data_addr = format!("{}:{}", host, port);
+    let mut data = TcpStream::connect(&data_addr)
+        .await
+        .map_err(|err| format!("failed to open data connection to {}: {}", data_addr, err))?;
+    ftp_command(conn, command, command, &[125, 150]).await?;
+    let mut contents = Vec::new();
+    (&mut data)
+        .take(MAX_FILE + 1)
+        .read_to_end(&mut contents)
+        .await
+        .map_err(|err| format!("{} failed: {}", command, err))?;
+    if contents.len() as u64 > MAX_FILE {
+        return Err(format!("{} is over {} bytes", command, MAX_FILE));
+    }
+    drop(data);
+    let (code, text) = ftp_reply(conn).await?;
+    if code != 226 && code != 250 {
+        return Err(format!("{} failed with {} {}", command, code, text));
+    }
+    Ok(contents)
+}
+
+/// The port from a `227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)` reply
+fn parse_pasv(text: &str) -> Option<u16> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let numbers: Vec<u16> = text[start..]
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|n| !n.is_empty())
+        .take(6)
+        .map(|n| n.parse().ok())
+        .collect::<Option<_>>()?;
+    match numbers[..] {
+        [_, _, _, _, high, low] if high < 256 && low < 256 => Some(high * 256 + low),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const FILE: &[u8] = b"flag{team1}\n";
+
+    /// An FTP server with an anonymous login and a `pub` directory holding
+    /// `flag.txt`.
+    async fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    let send = |reply: &'static str| format!("{}\r\n", reply);
+                    stream
+                        .get_mut()
+                        .write_all(b"220-Welcome\r\nTeam 1 files\r\n220 vsFTPd ready\r\n")
+                        .await
+                        .unwrap();
+                    let mut data = None;
+                    let mut line = String::new();
+                    while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+                        let command = line.trim_end().to_string();
+                        line.clear();
+                        let (verb, arg) = command.split_once(' ').unwrap_or((&command, ""));
+                        let reply = match verb {
+                            "USER" if arg == "anonymous" => send("331 Send any password"),
+                            "USER" => send("530 Anonymous only"),
+                            "PASS" => send("230 Logged in"),
+                            "TYPE" => send("200 Type set"),
+                            "PASV" => {
+                                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                                let port = listener.local_addr().unwrap().port();
+                                data = Some(listener);
+                                // A wrong address like servers behind NAT send
+                                format!(
+                                    "227 Entering Passive Mode (10,9,8,7,{},{})\r\n",
+                                    port / 256,
+                                    port % 256
+                                )
+                            }
+                            "NLST" | "RETR" => {
+                                let contents: &[u8] = match (verb, arg) {
+                                    ("NLST", "pub") => b"pub/flag.txt\r\npub/notes\r\n",
+                                    ("RETR", "pub/flag.txt") => FILE,
+                                    _ => {
+                                        let reply = b"550 No such file\r\n";
+                                        stream.get_mut().write_all(reply).await.unwrap();
+                                        continue;
+                                    }
+                                };
+                                let (mut conn, _) = data.take().unwrap().accept().await.unwrap();
+                                stream
+                                    .get_mut()
+                                    .write_all(b"150 Here it comes\r\n")
+                                    .await
+                                    .unwrap();
+                                conn.write_all(contents).await.unwrap();
+                                drop(conn);
+                                send("226 Transfer complete")
+                            }
+                            "QUIT" => send("221 Bye"),
+                            _ => send("502 Not implemented"),
+                        };
+                        stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn list_and_retrieve() {
+        let env = vec![("FTP".to_string(), serve().await)];
+        let run = |yaml: String| {
+            let check: FtpCheck = serde_yaml::from_str(&yaml).unwrap();
+            assert!(check.is_valid());
+            let env = env.clone();
+            async move { check.run(&env, None).await }
+        };
+        let hash = format!("{:x}", Sha256::digest(FILE));
+
+        assert_eq!(
+            run(format!(
+                "{{server: $FTP, banner: Team 1, list: pub, contains: flag.txt, retrieve: pub/flag.txt, sha256: {}}}",
+                hash
+            ))
+            .await,
+            Ok(format!(
+                "logged in to {} as anonymous, listed 2 files in pub, retrieved pub/flag.txt (12 bytes)",
+                env[0].1
+            ))
+        );
+        assert_eq!(
+            run("{server: $FTP, list: pub, contains: [flag.txt, secret]}".to_string()).await,
+            Err("pub does not contain secret".to_string())
+        );
+        assert_eq!(
+            run(format!(
+                "{{server: $FTP, retrieve: pub/flag.txt, sha256: {}}}",
+                "0".repeat(64)
+            ))
+            .await,
+            Err(format!(
+                "pub/flag.txt has sha256 {}, expected {}",
+                hash,
+                "0".repeat(64)
+            ))
+        );
+        assert_eq!(
+            run("{server: $FTP, retrieve: missing}".to_string()).await,
+            Err(format!(
+                "{} replied to RETR missing with 550 No such file",
+                env[0].1
+            ))
+        );
+    }
+
+    #[test]
+    fn passive_ports() {
+        assert_eq!(
+            parse_pasv("Entering Passive Mode (10,0,0,1,4,1)."),
+            Some(1025)
+        );
+        assert_eq!(parse_pasv("Entering Passive Mode (10,0,0,1,4)"), None);
+    }
+}
@@ -0,0 +1,342 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use regex::Regex;
+use reqwest::{redirect::Policy, Method, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{describe_error, CheckResult, OneOrMany};
+use crate::checker::{env::substitute, executor::executor};
+
+/// Requests a page and checks the response. The url, headers, body and
+/// `contains` can use the team's variables like `$WEB_SERVER`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HttpCheck {
+    pub url: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    /// Headers sent with the request
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Accepted status codes. Any 2xx status if not set.
+    #[serde(default)]
+    pub status: Option<OneOrMany<u16>>,
+    /// Text the response body must contain
+    #[serde(default)]
+    pub contains: Option<String>,
+    /// Pattern the response body must match
+    #[serde(default)]
+    pub regex: Option<String>,
+    /// Headers the response must have, each containing the given text
+    #[serde(default)]
+    pub response_headers: BTreeMap<String, String>,
+    /// Values that must be in a JSON response
+    #[serde(default)]
+    pub json: Vec<JsonAssertion>,
+    /// How many redirects to follow. 0 returns the redirect itself.
+    #[serde(default = "default_redirects")]
+    pub redirects: usize,
+    /// Reject invalid or self-signed certificates
+    #[serde(default = "default_verify_tls")]
+    pub verify_tls: bool,
+}
+
+/// A value at a path like `user.roles[0]` in a JSON response. The value only
+/// has to exist if `equals` isn't set.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JsonAssertion {
+    pub path: String,
+    #[serde(default)]
+    pub equals: Option<Value>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn default_redirects() -> usize {
+    10
+}
+
+fn default_verify_tls() -> bool {
+    true
+}
+
+impl HttpCheck {
+    pub fn is_valid(&self) -> bool {
+        !self.url.is_empty()
+            && Method::from_bytes(self.method.as_bytes()).is_ok()
+            && self.status.as_ref().is_none_or(|s| {
+                !s.as_slice().is_empty() && s.as_slice().iter().all(|c| (100..600).contains(c))
+            })
+            && self.regex.as_ref().is_none_or(|r| Regex::new(r).is_ok())
+            && self.json.iter().all(|j| parse_path(&j.path).is_some())
+    }
+    pub async fn run(&self, env: &[(String, String)], timeout: Duration) -> CheckResult {
+        self.fetch(env, timeout).await.map(|(message, _)| message)
+    }
+    /// Makes the request and checks the response, returning a description
+    /// of it along with the body.
+    pub async fn fetch(
+        &self,
+        env: &[(String, String)],
+        timeout: Duration,
+    ) -> Result<(String, String), String> {
+        let url = substitute(&self.url, env);
+        let method = Method::from_bytes(self.method.as_bytes())
+            .map_err(|_| format!("invalid method {}", self.method))?;
+        let redirects = match self.redirects {
+            0 => Policy::none(),
+            n => Policy::limited(n),
+        };
+        let client = reqwest::Client::builder()
+            .redirect(redirects)
+            .danger_accept_invalid_certs(!self.verify_tls)
+            .timeout(timeout)
+            .build()
+            .map_err(|err| describe_error(&err))?;
+        let mut request = client.request(method.clone(), &url);
+        for (name, value) in &self.headers {
+            request = request.header(name, substitute(value, env));
+        }
+        if let Some(body) = &self.body {
+            request = request.body(substitute(body, env));
+        }
+        let response = request.send().await.map_err(|err| {
+            format!(
+                "{} {} failed: {}",
+                method,
+                url,
+                describe_error(&err.without_url())
+            )
+        })?;
+
+        let status = response.status();
+        let accepted = match &self.status {
+            Some(codes) => codes.as_slice().contains(&status.as_u16()),
+            None => status.is_success(),
+        };
+        if !accepted {
+            let expected = match &self.status {
+                Some(codes) => codes
+                    .as_slice()
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" or "),
+                None => "2xx".to_string(),
+            };
+            let mut reason = format!("expected status {}, got {}", expected, status);
+            if let Some(location) = response.headers().get("location") {
+                reason += &format!(
+                    " redirecting to {}",
+                    String::from_utf8_lossy(location.as_bytes())
+                );
+            }
+            return Err(reason);
+        }
+        for (name, expected) in &self.response_headers {
+            let Some(value) = response.headers().get(name) else {
+                return Err(format!("missing header {}", name));
+            };
+            let value = String::from_utf8_lossy(value.as_bytes());
+            if !value.contains(expected.as_str()) {
+                return Err(format!(
+                    "header {} is {:?}, expected it to contain {:?}",
+                    name, value, expected
+                ));
+            }
+        }
+
+        let body = read_body(response, executor().max_output()).await.map_err(|err| {
+            format!(
+                "failed to read body: {}",
+                describe_error(&err.without_url())
+            )
+        })?;
+        if let Some(expected) = &self.contains {
+            let expected = substitute(expected, env);
+            if !body.contains(&expected) {
+                return Err(format!("body does not contain {:?}", expected));
+            }
+        }
+        if let Some(pattern) = &self.regex {
+            let regex = Regex::new(pattern).map_err(|err| format!("invalid regex: {}", err))?;
+            if !regex.is_match(&body) {
+                return Err(format!("body does not match /{}/", pattern));
+            }
+        }
+        if !self.json.is_empty() {
+            let json: Value =
+                serde_json::from_str(&body).map_err(|err| format!("body is not JSON: {}", err))?;
+            for assertion in &self.json {
+                assertion.check(&json)?;
+            }
+        }
+        Ok((format!("{} {}", status, url), body))
+    }
+}
+
+/// Reads the body up to `max` bytes, the same cap as checker output, and
+/// leaves the rest unread.
+async fn read_body(mut response: Response, max: usize) -> Result<String, reqwest::Error> {
+    let mut body = Vec::new();
+    while body.len() < max {
+        let Some(chunk) = response.chunk().await? else {
+            break;
+        };
+        let room = max - body.len();
+        body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+impl JsonAssertion {
+    fn check(&self, json: &Value) -> Result<(), String> {
+        let segments =
+            parse_path(&self.path).ok_or_else(|| format!("invalid json path {}", self.path))?;
+        let mut value = json;
+        for segment in segments {
+            let next = match segment {
+                PathSegment::Key(key) => value.get(key),
+                PathSegment::Index(index) => value.get(index),
+            };
+            value = next.ok_or_else(|| format!("json has nothing at {}", self.path))?;
+        }
+        match &self.equals {
+            Some(expected) if expected != value => Err(format!(
+                "json {} is {}, expected {}",
+                self.path, value, expected
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum PathSegment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// Splits a path like `$.users[0].name` into keys and array indexes.
+fn parse_path(path: &str) -> Option<Vec<PathSegment<'_>>> {
+    let path = path.strip_prefix('$').unwrap_or(path);
+    let path = path.strip_prefix('.').unwrap_or(path);
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (key, mut indexes) = match part.find('[') {
+            Some(bracket) => part.split_at(bracket),
+            None => (part, ""),
+        };
+        if !key.is_empty() {
+            segments.push(PathSegment::Key(key));
+        } else if indexes.is_empty() {
+            return None;
+        }
+        while !indexes.is_empty() {
+            let rest = indexes.strip_prefix('[')?;
+            let end = rest.find(']')?;
+            segments.push(PathSegment::Index(rest[..end].parse().ok()?));
+            indexes = &rest[end + 1..];
+        }
+    }
+    Some(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{response::Redirect, routing::get, Json, Router};
+
+    async fn serve() -> String {
+        let app = Router::new()
+            .route("/", get(|| async { "Welcome to team 3" }))
+            .route("/old", get(|| async { Redirect::permanent("/") }))
+            .route("/big", get(|| async { "team 3 ".repeat(1 << 20) }))
+            .route(
+                "/api",
+                get(|| async { Json(serde_json::json!({"users": [{"name": "admin"}]})) }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        addr.to_string()
+    }
+
+    fn check(yaml: &str) -> HttpCheck {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn parse_json_paths() {
+        assert_eq!(
+            parse_path("$.users[0].name"),
+            Some(vec![
+                PathSegment::Key("users"),
+                PathSegment::Index(0),
+                PathSegment::Key("name")
+            ])
+        );
+        assert_eq!(
+            parse_path("[1][2]"),
+            Some(vec![PathSegment::Index(1), PathSegment::Index(2)])
+        );
+        assert_eq!(parse_path("users..name"), None);
+        assert_eq!(parse_path("users[x]"), None);
+    }
+
+    #[tokio::test]
+    async fn check_local_server() {
+        let env = vec![
+            ("WEB".to_string(), serve().await),
+            ("TEAM".to_string(), "3".to_string()),
+        ];
+        let timeout = Duration::from_secs(5);
+        let run = |yaml: &str| {
+            let check = check(yaml);
+            assert!(check.is_valid());
+            let env = env.clone();
+            async move { check.run(&env, timeout).await }
+        };
+
+        assert!(
+            run("{url: 'http://$WEB/old', contains: 'team $TEAM', regex: 'team [0-9]'}")
+                .await
+                .is_ok()
+        );
+        assert!(run("{url: 'http://$WEB/api', json: [{path: 'users[0].name', equals: admin}], response_headers: {content-type: json}}")
+            .await
+            .is_ok());
+        assert_eq!(
+            run("{url: 'http://$WEB/old', redirects: 0}").await,
+            Err("expected status 2xx, got 308 Permanent Redirect redirecting to /".to_string())
+        );
+        assert_eq!(
+            run("{url: 'http://$WEB/missing', status: [200, 301]}").await,
+            Err("expected status 200 or 301, got 404 Not Found".to_string())
+        );
+        assert_eq!(
+            run("{url: 'http://$WEB/', contains: 'team 4'}").await,
+            Err("body does not contain \"team 4\"".to_string())
+        );
+        assert_eq!(
+            run("{url: 'http://$WEB/api', json: [{path: 'users[0].name', equals: root}]}").await,
+            Err("json users[0].name is \"admin\", expected \"root\"".to_string())
+        );
+        assert!(run("{url: 'http://127.0.0.1:1/'}")
+            .await
+            .unwrap_err()
+            .starts_with("GET http://127.0.0.1:1/ failed: "));
+    }
+    #[tokio::test]
+    async fn cap_the_body() {
+        let url = format!("http://{}/big", serve().await);
+        let response = reqwest::get(&url).await.unwrap();
+        let body = read_body(response, 1000).await.unwrap();
+        assert_eq!(body.len(), 1000);
+        assert!(body.starts_with("team 3 team 3"));
+    }
+}
@@ -0,0 +1,153 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{team_login, FtpCheck, HttpCheck};
+use crate::checker::service::CheckStatus;
+
+/// Watches content for defacement. The first time a team is checked the
+/// hash of the content is kept as their baseline, and later checks compare
+/// against it until an admin takes a new one.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct IntegrityCheck {
+    pub source: ContentSource,
+    /// Patterns removed from the content before hashing, for parts that
+    /// change on their own like timestamps
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// What the service counts as when the content has changed
+    #[serde(default)]
+    pub on_change: OnChange,
+}
+
+/// Where the watched content comes from. Everything the http and ftp checks
+/// test still has to pass before the content is compared.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentSource {
+    /// The body of a page
+    Http(HttpCheck),
+    /// The file given in `retrieve`
+    Ftp(FtpCheck),
+    /// The output of a bash command run like a checker
+    Command(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OnChange {
+    #[default]
+    Down,
+    Degraded,
+}
+
+/// The hash of a team's content when it was known to be good.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Baseline {
+    /// Hex SHA-256 of the content with the ignored parts removed
+    pub hash: String,
+    pub size: usize,
+    /// Milliseconds since the epoch
+    pub taken_at: u128,
+}
+
+impl IntegrityCheck {
+    pub fn is_valid(&self) -> bool {
+        let source = match &self.source {
+            ContentSource::Http(check) => check.is_valid(),
+            ContentSource::Ftp(check) => check.is_valid() && check.retrieve.is_some(),
+            ContentSource::Command(command) => !command.is_empty(),
+        };
+        source
+            && self
+                .ignore
+                .iter()
+                .all(|pattern| Regex::new(pattern).is_ok())
+    }
+    /// Gets the content from an http or ftp source. Commands are run by
+    /// the service since they need its sandbox.
+    pub async fn fetch(
+        &self,
+        team: &str,
+        env: &[(String, String)],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, String> {
+        match &self.source {
+            ContentSource::Http(check) => Ok(check.fetch(env, timeout).await?.1.into_bytes()),
+            ContentSource::Ftp(check) => {
+                let login = match &check.credentials {
+                    Some(group) => Some(team_login(team, group, check.user.as_deref())?),
+                    None => None,
+                };
+                check
+                    .fetch(env, login.as_ref())
+                    .await?
+                    .1
+                    .ok_or_else(|| "nothing was retrieved".to_string())
+            }
+            ContentSource::Command(_) => Err("commands can't be fetched".to_string()),
+        }
+    }
+    pub fn snapshot(&self, content: &[u8]) -> Baseline {
+        let mut content = content.to_vec();
+        for pattern in &self.ignore {
+            if let Ok(regex) = Regex::new(pattern) {
+                content = regex.replace_all(&content, &b""[..]).into_owned();
+            }
+        }
+        Baseline {
+            hash: format!("{:x}", Sha256::digest(&content)),
+            size: content.len(),
+            taken_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+        }
+    }
+    /// Compares content against the baseline. Returns the status and a
+    /// reason if it changed.
+    pub fn compare(
+        &self,
+        snapshot: &Baseline,
+        baseline: &Baseline,
+    ) -> Result<(), (CheckStatus, String)> {
+        if snapshot.hash == baseline.hash {
+            return Ok(());
+        }
+        let status = match self.on_change {
+            OnChange::Down => CheckStatus::Down,
+            OnChange::Degraded => CheckStatus::Degraded,
+        };
+        Err((
+            status,
+            format!(
+                "content changed from the baseline: sha256 {} ({} bytes), expected {} ({} bytes)",
+                snapshot.hash, snapshot.size, baseline.hash, baseline.size
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignored_parts_dont_change_the_hash() {
+        let check: IntegrityCheck = serde_json::from_str(
+            r#"{"source": {"command": "cat index.html"}, "ignore": ["generated at [0-9:]+"], "on_change": "degraded"}"#,
+        )
+        .unwrap();
+        assert!(check.is_valid());
+        let baseline = check.snapshot(b"<h1>Team 1</h1> generated at 12:00:01");
+        let later = check.snapshot(b"<h1>Team 1</h1> generated at 12:05:42");
+        assert_eq!(check.compare(&later, &baseline), Ok(()));
+
+        let defaced = check.snapshot(b"<h1>pwned</h1> generated at 12:06:00");
+        let (status, reason) = check.compare(&defaced, &baseline).unwrap_err();
+        assert_eq!(status, CheckStatus::Degraded);
+        assert!(reason.starts_with("content changed from the baseline"));
+    }
+}
@@ -0,0 +1,697 @@
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+
+use super::{check_banner, team_login, with_default_port, CheckResult, LineConnection};
+use crate::checker::{env::substitute, password::UserPass};
+
+/// Talks to a team's SMTP server, optionally logging in and sending a
+/// message that is then looked for in a mailbox.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SmtpCheck {
+    /// Server to connect to, with port 25 unless one is given
+    pub server: String,
+    /// Text the server's greeting must contain
+    #[serde(default)]
+    pub banner: Option<String>,
+    /// Password group to log in with
+    #[serde(default)]
+    pub credentials: Option<String>,
+    /// User from the password group. A random one if not set.
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub send: Option<SendMail>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SendMail {
+    pub from: String,
+    pub to: String,
+    /// Mailbox the message should show up in
+    #[serde(default)]
+    pub confirm: Option<Mailbox>,
+    /// Seconds to keep looking for the message in the mailbox
+    #[serde(default = "default_wait")]
+    pub wait: u64,
+}
+
+fn default_wait() -> u64 {
+    5
+}
+
+/// Time left at the end of a check to report that mail didn't arrive
+/// before the service's timeout cuts it off
+const CONFIRM_MARGIN: Duration = Duration::from_millis(500);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mailbox {
+    Imap(MailboxCheck),
+    Pop3(MailboxCheck),
+}
+
+/// Connects to a POP3 or IMAP server and checks its greeting, logging in if
+/// credentials are given.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MailboxCheck {
+    /// Server to connect to, with the protocol's port unless one is given
+    pub server: String,
+    #[serde(default)]
+    pub banner: Option<String>,
+    #[serde(default)]
+    pub credentials: Option<String>,
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Protocol {
+    Pop3,
+    Imap,
+}
+
+impl SmtpCheck {
+    pub fn is_valid(&self) -> bool {
+        !self.server.is_empty()
+            && self.send.as_ref().is_none_or(|send| {
+                !send.from.is_empty()
+                    && !send.to.is_empty()
+                    && send.confirm.as_ref().is_none_or(|c| c.is_valid())
+            })
+    }
+    /// Talks to the server, logging in with `login` if given. `team` is
+    /// for the confirm mailbox's own login. Waiting for mail to arrive stops
+    /// short of `timeout` so it can be reported.
+    pub async fn run(
+        &self,
+        team: &str,
+        env: &[(String, String)],
+        login: Option<&UserPass>,
+        timeout: Duration,
+    ) -> CheckResult {
+        let start = Instant::now();
+        let server = with_default_port(&substitute(&self.server, env), 25);
+        let banner = self.banner.as_ref().map(|b| substitute(b, env));
+        let Some(send) = &self.send else {
+            smtp_session(&server, banner.as_deref(), login, None).await?;
+            return Ok(format!("{} is accepting mail", server));
+        };
+        let mail = Mail {
+            from: substitute(&send.from, env),
+            to: substitute(&send.to, env),
+            token: format!("{:016x}", rand::random::<u64>()),
+        };
+        smtp_session(&server, banner.as_deref(), login, Some(&mail)).await?;
+        let Some(mailbox) = &send.confirm else {
+            return Ok(format!("{} accepted mail to {}", server, mail.to));
+        };
+        let left = timeout
+            .saturating_sub(start.elapsed())
+            .saturating_sub(CONFIRM_MARGIN);
+        let waiting = Instant::now();
+        let deadline = waiting + Duration::from_secs(send.wait).min(left);
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            // Running out of time looking is the same as not finding it
+            let search = mailbox.find(team, env, &mail.token);
+            let found = match tokio::time::timeout(left, search).await {
+                Ok(found) => found?,
+                Err(_) => false,
+            };
+            if found {
+                return Ok(format!("mail to {} arrived", mail.to));
+            }
+            if Instant::now() + Duration::from_secs(1) >= deadline {
+                return Err(format!(
+                    "mail to {} did not arrive within {} seconds",
+                    mail.to,
+                    waiting.elapsed().as_secs()
+                ));
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+}
+
+impl Mailbox {
+    fn is_valid(&self) -> bool {
+        match self {
+            Mailbox::Imap(check) | Mailbox::Pop3(check) => check.is_valid(),
+        }
+    }
+    /// Whether a message with `token` in its subject is in the mailbox. It
+    /// is deleted if found.
+    async fn find(
+        &self,
+        team: &str,
+        env: &[(String, String)],
+        token: &str,
+    ) -> Result<bool, String> {
+        let (protocol, check) = match self {
+            Mailbox::Imap(check) => (Protocol::Imap, check),
+            Mailbox::Pop3(check) => (Protocol::Pop3, check),
+        };
+        let login = match &check.credentials {
+            Some(group) => Some(team_login(team, group, check.user.as_deref())?),
+            None => None,
+        };
+        check.session(protocol, env, login.as_ref(), Some(token)).await
+    }
+}
+
+impl MailboxCheck {
+    pub fn is_valid(&self) -> bool {
+        !self.server.is_empty()
+    }
+    pub async fn run_pop3(
+        &self,
+        env: &[(String, String)],
+        login: Option<&UserPass>,
+    ) -> CheckResult {
+        self.session(Protocol::Pop3, env, login, None).await?;
+        Ok(format!("{} is up", substitute(&self.server, env)))
+    }
+    pub async fn run_imap(
+        &self,
+        env: &[(String, String)],
+        login: Option<&UserPass>,
+    ) -> CheckResult {
+        self.session(Protocol::Imap, env, login, None).await?;
+        Ok(format!("{} is up", substitute(&self.server, env)))
+    }
+    async fn session(
+        &self,
+        protocol: Protocol,
+        env: &[(String, String)],
+        login: Option<&UserPass>,
+        token: Option<&str>,
+    ) -> Result<bool, String> {
+        let port = match protocol {
+            Protocol::Pop3 => 110,
+            Protocol::Imap => 143,
+        };
+        let server = with_default_port(&substitute(&self.server, env), port);
+        let banner = self.banner.as_ref().map(|b| substitute(b, env));
+        match protocol {
+            Protocol::Pop3 => pop3_session(&server, banner.as_deref(), login, token).await,
+            Protocol::Imap => imap_session(&server, banner.as_deref(), login, token).await,
+        }
+    }
+}
+
+struct Mail {
+    from: String,
+    to: String,
+    /// Put in the subject to find the message again
+    token: String,
+}
+
+/// Reads a possibly multi-line SMTP reply
+async fn smtp_reply(conn: &mut LineConnection) -> Result<(u16, String), String> {
+    let mut text = Vec::new();
+    loop {
+        let line = conn.read_line().await?;
+        let code = line
+            .get(..3)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| format!("{} sent an invalid reply {:?}", conn.server, line))?;
+        text.push(line.get(4..).unwrap_or("").to_string());
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok((code, text.join(" ")));
+        }
+    }
+}
+
+/// Sends a command and fails unless the reply has one of the expected
+/// codes. `name` is used in errors in place of the command so passwords
+/// aren't shown.
+async fn smtp_command(
+    conn: &mut LineConnection,
+    command: &str,
+    name: &str,
+    expected: &[u16],
+) -> Result<String, String> {
+    conn.write_line(command).await?;
+    let (code, text) = smtp_reply(conn).await?;
+    if !expected.contains(&code) {
+        return Err(format!(
+            "{} replied to {} with {} {}",
+            conn.server, name, code, text
+        ));
+    }
+    Ok(text)
+}
+
+async fn smtp_session(
+    server: &str,
+    banner: Option<&str>,
+    login: Option<&UserPass>,
+    mail: Option<&Mail>,
+) -> Result<(), String> {
+    let mut conn = LineConnection::connect(server).await?;
+    let (code, greeting) = smtp_reply(&mut conn).await?;
+    if code != 220 {
+        return Err(format!("{} greeted with {} {}", server, code, greeting));
+    }
+    check_banner(&greeting, banner)?;
+    if smtp_command(&mut conn, "EHLO scoreboard", "EHLO", &[250])
+        .await
+        .is_err()
+    {
+        smtp_command(&mut conn, "HELO scoreboard", "HELO", &[250]).await?;
+    }
+    if let Some(login) = login {
+        let plain = STANDARD.encode(format!("\0{}\0{}", login.username, login.password));
+        smtp_command(&mut conn, &format!("AUTH PLAIN {}", plain), "AUTH", &[235])
+            .await
+            .map_err(|err| format!("login as {} failed: {}", login.username, err))?;
+    }
+    if let Some(mail) = mail {
+        let from = format!("MAIL FROM:<{}>", mail.from);
+        smtp_command(&mut conn, &from, &from, &[250]).await?;
+        let to = format!("RCPT TO:<{}>", mail.to);
+        smtp_command(&mut conn, &to, &to, &[250, 251]).await?;
+        smtp_command(&mut conn, "DATA", "DATA", &[354]).await?;
+        for line in [
+            format!("From: <{}>", mail.from),
+            format!("To: <{}>", mail.to),
+            format!("Subject: Scoreboard check {}", mail.token),
+            String::new(),
+            "Sent by the scoreboard to check that mail is delivered.".to_string(),
+        ] {
+            conn.write_line(&line).await?;
+        }
+        smtp_command(&mut conn, ".", "message", &[250]).await?;
+    }
+    let _ = smtp_command(&mut conn, "QUIT", "QUIT", &[221]).await;
+    Ok(())
+}
+
+/// Sends a POP3 command and returns the rest of the `+OK` line
+async fn pop3_command(
+    conn: &mut LineConnection,
+    command: &str,
+    name: &str,
+) -> Result<String, String> {
+    conn.write_line(command).await?;
+    let reply = conn.read_line().await?;
+    match reply.strip_prefix("+OK") {
+        Some(rest) => Ok(rest.trim().to_string()),
+        None => Err(format!(
+            "{} replied to {} with {}",
+            conn.server, name, reply
+        )),
+    }
+}
+
+/// Reads a multi-line POP3 response up to the lone `.`
+async fn pop3_lines(conn: &mut LineConnection) -> Result<Vec<String>, String> {
+    let mut lines = Vec::new();
+    loop {
+        let line = conn.read_line().await?;
+        if line == "." {
+            return Ok(lines);
+        }
+        lines.push(line);
+    }
+}
+
+/// How many of the newest messages to look through for the token
+const SEARCH_DEPTH: usize = 20;
+
+async fn pop3_session(
+    server: &str,
+    banner: Option<&str>,
+    login: Option<&UserPass>,
+    token: Option<&str>,
+) -> Result<bool, String> {
+    let mut conn = LineConnection::connect(server).await?;
+    let greeting = conn.read_line().await?;
+    if !greeting.starts_with("+OK") {
+        return Err(format!("{} greeted with {}", server, greeting));
+    }
+    check_banner(&greeting, banner)?;
+    if let Some(login) = login {
+        let user = format!("USER {}", login.username);
+        let pass = format!("PASS {}", login.password);
+        let logged_in = match pop3_command(&mut conn, &user, "USER").await {
+            Ok(_) => pop3_command(&mut conn, &pass, "PASS").await,
+            Err(err) => Err(err),
+        };
+        logged_in.map_err(|err| format!("login as {} failed: {}", login.username, err))?;
+    }
+    let mut found = false;
+    if let Some(token) = token {
+        let stat = pop3_command(&mut conn, "STAT", "STAT").await?;
+        let count: usize = stat
+            .split_whitespace()
+            .next()
+            .and_then(|count| count.parse().ok())
+            .ok_or_else(|| format!("{} sent an invalid STAT reply {:?}", server, stat))?;
+        for message in (1..=count).rev().take(SEARCH_DEPTH) {
+            pop3_command(&mut conn, &format!("RETR {}", message), "RETR").await?;
+            if pop3_lines(&mut conn)
+                .await?
+                .iter()
+                .any(|line| line.contains(token))
+            {
+                pop3_command(&mut conn, &format!("DELE {}", message), "DELE").await?;
+                found = true;
+                break;
+            }
+        }
+    }
+    let _ = pop3_command(&mut conn, "QUIT", "QUIT").await;
+    Ok(found)
+}
+
+/// Sends a tagged IMAP command and returns the untagged lines before the
+/// tagged OK.
+async fn imap_command(
+    conn: &mut LineConnection,
+    tag: &str,
+    command: &str,
+    name: &str,
+) -> Result<Vec<String>, String> {
+    conn.write_line(&format!("{} {}", tag, command)).await?;
+    let mut lines = Vec::new();
+    loop {
+        let line = conn.read_line().await?;
+        let Some(status) = line.strip_prefix(tag).and_then(|l| l.strip_prefix(' ')) else {
+            lines.push(line);
+            continue;
+        };
+        if status.starts_with("OK") {
+            return Ok(lines);
+        }
+        return Err(format!(
+            "{} replied to {} with {}",
+            conn.server, name, status
+        ));
+    }
+}
+
+fn imap_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+async fn imap_session(
+    server: &str,
+    banner: Option<&str>,
+    login: Option<&UserPass>,
+    token: Option<&str>,
+) -> Result<bool, String> {
+    let mut conn = LineConnection::connect(server).await?;
+    let greeting = conn.read_line().await?;
+    if !greeting.starts_with("* OK") && !greeting.starts_with("* PREAUTH") {
+        return Err(format!("{} greeted with {}", server, greeting));
+    }
+    check_banner(&greeting, banner)?;
+    if let Some(login) = login {
+        let command = format!(
+            "LOGIN {} {}",
+            imap_quote(&login.username),
+            imap_quote(&login.password)
+        );
+        imap_command(&mut conn, "a1", &command, "LOGIN")
+            .await
+            .map_err(|err| format!("login as {} failed: {}", login.username, err))?;
+    }
+    let mut found = false;
+    if let Some(token) = token {
+        imap_command(&mut conn, "a2", "SELECT INBOX", "SELECT").await?;
+        let search = format!("SEARCH SUBJECT {}", imap_quote(token));
+        let lines = imap_command(&mut conn, "a3", &search, "SEARCH").await?;
+        let ids: Vec<&str> = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("* SEARCH"))
+            .flat_map(|ids| ids.split_whitespace())
+            .collect();
+        if !ids.is_empty() {
+            let store = format!("STORE {} +FLAGS (\\Deleted)", ids.join(","));
+            imap_command(&mut conn, "a4", &store, "STORE").await?;
+            imap_command(&mut conn, "a5", "EXPUNGE", "EXPUNGE").await?;
+            found = true;
+        }
+    } else if login.is_some() {
+        imap_command(&mut conn, "a2", "EXAMINE INBOX", "EXAMINE").await?;
+    }
+    let _ = imap_command(&mut conn, "a9", "LOGOUT", "LOGOUT").await;
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    type Inbox = Arc<Mutex<Vec<String>>>;
+
+    fn alice() -> UserPass {
+        UserPass {
+            username: "alice".to_string(),
+            password: "secret".to_string(),
+        }
+    }
+
+    /// Accepts connections on a local port and hands each one to `handle`
+    async fn serve<F, Fut>(inbox: &Inbox, handle: F) -> String
+    where
+        F: Fn(BufReader<TcpStream>, Inbox) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let inbox = inbox.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle(BufReader::new(stream), inbox.clone()));
+            }
+        });
+        addr
+    }
+
+    async fn send(stream: &mut BufReader<TcpStream>, lines: &[&str]) {
+        for line in lines {
+            let line = format!("{}\r\n", line);
+            stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+        }
+    }
+
+    async fn smtp_server(mut stream: BufReader<TcpStream>, inbox: Inbox) {
+        send(&mut stream, &["220 mail.test ESMTP fake"]).await;
+        let plain = STANDARD.encode("\0alice\0secret");
+        let mut line = String::new();
+        while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+            let command = line.trim_end().to_string();
+            line.clear();
+            let reply = match command.split(' ').next().unwrap_or("") {
+                "EHLO" => "250-mail.test\r\n250 AUTH PLAIN",
+                "AUTH" if command.ends_with(&plain) => "235 ok",
+                "AUTH" => "535 bad credentials",
+                "MAIL" | "RCPT" => "250 ok",
+                "DATA" => {
+                    send(&mut stream, &["354 go ahead"]).await;
+                    let mut message = String::new();
+                    while stream.read_line(&mut line).await.unwrap() > 0 && line != ".\r\n" {
+                        message.push_str(&line);
+                        line.clear();
+                    }
+                    line.clear();
+                    inbox.lock().unwrap().push(message);
+                    "250 queued"
+                }
+                "QUIT" => {
+                    send(&mut stream, &["221 bye"]).await;
+                    return;
+                }
+                _ => "502 unknown",
+            };
+            send(&mut stream, &[reply]).await;
+        }
+    }
+
+    async fn imap_server(mut stream: BufReader<TcpStream>, inbox: Inbox) {
+        send(&mut stream, &["* OK IMAP ready"]).await;
+        let mut line = String::new();
+        while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+            let command = line.trim_end().to_string();
+            line.clear();
+            let mut parts = command.splitn(3, ' ');
+            let tag = parts.next().unwrap_or("");
+            let name = parts.next().unwrap_or("");
+            let args = parts.next().unwrap_or("");
+            let ok = format!("{} OK done", tag);
+            match name {
+                "LOGIN" if args == "\"alice\" \"secret\"" => send(&mut stream, &[&ok]).await,
+                "LOGIN" => send(&mut stream, &[&format!("{} NO bad login", tag)]).await,
+                "SEARCH" => {
+                    let token = args.trim_start_matches("SUBJECT ").trim_matches('"');
+                    let ids: Vec<String> = inbox
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, m)| m.contains(token))
+                        .map(|(i, _)| (i + 1).to_string())
+                        .collect();
+                    send(&mut stream, &[&format!("* SEARCH {}", ids.join(" ")), &ok]).await;
+                }
+                "EXPUNGE" => {
+                    inbox.lock().unwrap().clear();
+                    send(&mut stream, &[&ok]).await;
+                }
+                "LOGOUT" => {
+                    send(&mut stream, &["* BYE", &ok]).await;
+                    return;
+                }
+                _ => send(&mut stream, &[&ok]).await,
+            }
+        }
+    }
+
+    async fn pop3_server(mut stream: BufReader<TcpStream>, inbox: Inbox) {
+        send(&mut stream, &["+OK POP3 ready"]).await;
+        let mut line = String::new();
+        while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+            let command = line.trim_end().to_string();
+            line.clear();
+            let (name, arg) = command.split_once(' ').unwrap_or((&command, ""));
+            match name {
+                "USER" => send(&mut stream, &["+OK"]).await,
+                "PASS" if arg == "secret" => send(&mut stream, &["+OK logged in"]).await,
+                "PASS" => send(&mut stream, &["-ERR bad login"]).await,
+                "STAT" => {
+                    let count = inbox.lock().unwrap().len();
+                    send(&mut stream, &[&format!("+OK {} 0", count)]).await;
+                }
+                "RETR" => {
+                    let index: usize = arg.parse().unwrap();
+                    let message = inbox.lock().unwrap()[index - 1].clone();
+                    send(&mut stream, &["+OK", message.trim_end(), "."]).await;
+                }
+                "DELE" => {
+                    let index: usize = arg.parse().unwrap();
+                    inbox.lock().unwrap().remove(index - 1);
+                    send(&mut stream, &["+OK deleted"]).await;
+                }
+                "QUIT" => {
+                    send(&mut stream, &["+OK bye"]).await;
+                    return;
+                }
+                _ => send(&mut stream, &["-ERR unknown"]).await,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_and_confirm() {
+        let inbox = Inbox::default();
+        let smtp = serve(&inbox, smtp_server).await;
+        let imap = serve(&inbox, imap_server).await;
+        let pop3 = serve(&inbox, pop3_server).await;
+        let mail = |token: &str| Mail {
+            from: "scoreboard@test".to_string(),
+            to: "alice@team1.test".to_string(),
+            token: token.to_string(),
+        };
+
+        smtp_session(&smtp, Some("ESMTP"), Some(&alice()), Some(&mail("first")))
+            .await
+            .unwrap();
+        assert_eq!(
+            imap_session(&imap, None, Some(&alice()), Some("missing")).await,
+            Ok(false)
+        );
+        assert_eq!(
+            imap_session(&imap, None, Some(&alice()), Some("first")).await,
+            Ok(true)
+        );
+        assert!(inbox.lock().unwrap().is_empty());
+
+        smtp_session(&smtp, None, None, Some(&mail("second")))
+            .await
+            .unwrap();
+        assert_eq!(
+            pop3_session(&pop3, Some("POP3"), Some(&alice()), Some("second")).await,
+            Ok(true)
+        );
+        assert!(inbox.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn confirm_within_the_timeout() {
+        let inbox = Inbox::default();
+        let smtp = serve(&inbox, smtp_server).await;
+        // The mailbox never gets the message
+        let imap = serve(&Inbox::default(), imap_server).await;
+        let check = SmtpCheck {
+            server: smtp,
+            banner: None,
+            credentials: None,
+            user: None,
+            send: Some(SendMail {
+                from: "scoreboard@test".to_string(),
+                to: "alice@team1.test".to_string(),
+                confirm: Some(Mailbox::Imap(MailboxCheck {
+                    server: imap,
+                    banner: None,
+                    credentials: None,
+                    user: None,
+                })),
+                wait: default_wait(),
+            }),
+        };
+        let limit = Duration::from_secs(2);
+        let result = tokio::time::timeout(limit, check.run("team1", &[], Some(&alice()), limit))
+            .await
+            .expect("the check should give up before its timeout");
+        assert!(result.unwrap_err().contains("did not arrive"));
+    }
+
+    #[tokio::test]
+    async fn failure_reasons() {
+        let inbox = Inbox::default();
+        let smtp = serve(&inbox, smtp_server).await;
+        let imap = serve(&inbox, imap_server).await;
+        let pop3 = serve(&inbox, pop3_server).await;
+        let wrong = UserPass {
+            username: "alice".to_string(),
+            password: "wrong".to_string(),
+        };
+
+        assert_eq!(
+            smtp_session(&smtp, Some("Postfix"), None, None).await,
+            Err("banner \"mail.test ESMTP fake\" does not contain \"Postfix\"".to_string())
+        );
+        assert_eq!(
+            smtp_session(&smtp, None, Some(&wrong), None).await,
+            Err(format!(
+                "login as alice failed: {} replied to AUTH with 535 bad credentials",
+                smtp
+            ))
+        );
+        assert_eq!(
+            imap_session(&imap, None, Some(&wrong), None).await,
+            Err(format!(
+                "login as alice failed: {} replied to LOGIN with NO bad login",
+                imap
+            ))
+        );
+        assert_eq!(
+            pop3_session(&pop3, None, Some(&wrong), None).await,
+            Err(format!(
+                "login as alice failed: {} replied to PASS with -ERR bad login",
+                pop3
+            ))
+        );
+    }
+}
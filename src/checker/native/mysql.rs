@@ -0,0 +1,468 @@
+//! Just enough of the MySQL client protocol to log in and run a text query.
+
+use rsa::{pkcs8::DecodePublicKey, Oaep, RsaPublicKey};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::database::Row;
+use crate::checker::password::UserPass;
+
+const CLIENT_LONG_PASSWORD: u32 = 0x1;
+const CLIENT_CONNECT_WITH_DB: u32 = 0x8;
+const CLIENT_PROTOCOL_41: u32 = 0x200;
+const CLIENT_TRANSACTIONS: u32 = 0x2000;
+const CLIENT_SECURE_CONNECTION: u32 = 0x8000;
+const CLIENT_PLUGIN_AUTH: u32 = 0x80000;
+
+const NATIVE_PASSWORD: &str = "mysql_native_password";
+const CACHING_SHA2_PASSWORD: &str = "caching_sha2_password";
+
+/// Largest packet accepted from a server
+const MAX_PACKET: usize = 16 * 1024 * 1024;
+
+pub struct Connection {
+    stream: TcpStream,
+    server: String,
+    sequence: u8,
+    greeting: Greeting,
+}
+
+struct Greeting {
+    capabilities: u32,
+    scramble: Vec<u8>,
+    plugin: String,
+}
+
+impl Connection {
+    /// Connects and reads the server's greeting.
+    pub async fn connect(server: &str) -> Result<Self, String> {
+        let stream = TcpStream::connect(server)
+            .await
+            .map_err(|err| format!("failed to connect to {}: {}", server, err))?;
+        let mut conn = Connection {
+            stream,
+            server: server.to_string(),
+            sequence: 0,
+            greeting: Greeting {
+                capabilities: 0,
+                scramble: Vec::new(),
+                plugin: String::new(),
+            },
+        };
+        let packet = conn.read_packet().await?;
+        conn.greeting = parse_greeting(&packet)
+            .ok_or_else(|| format!("{} sent an invalid greeting", server))??;
+        Ok(conn)
+    }
+    pub async fn login(&mut self, login: &UserPass, database: Option<&str>) -> Result<(), String> {
+        let capabilities = CLIENT_LONG_PASSWORD
+            | CLIENT_PROTOCOL_41
+            | CLIENT_TRANSACTIONS
+            | CLIENT_SECURE_CONNECTION
+            | CLIENT_PLUGIN_AUTH
+            | database.map_or(0, |_| CLIENT_CONNECT_WITH_DB);
+        if self.greeting.capabilities & CLIENT_PROTOCOL_41 == 0 {
+            return Err(format!("{} is too old", self.server));
+        }
+        let capabilities = capabilities & self.greeting.capabilities;
+        let mut plugin = self.greeting.plugin.clone();
+        if plugin != CACHING_SHA2_PASSWORD {
+            plugin = NATIVE_PASSWORD.to_string();
+        }
+        let mut scramble = self.greeting.scramble.clone();
+        let auth = scramble_password(&plugin, &login.password, &scramble)?;
+
+        let mut packet = capabilities.to_le_bytes().to_vec();
+        packet.extend_from_slice(&(MAX_PACKET as u32).to_le_bytes());
+        // utf8mb4_general_ci
+        packet.push(45);
+        packet.extend_from_slice(&[0; 23]);
+        push_null_terminated(&mut packet, &login.username);
+        packet.push(auth.len() as u8);
+        packet.extend_from_slice(&auth);
+        if let Some(database) = database {
+            push_null_terminated(&mut packet, database);
+        }
+        if capabilities & CLIENT_PLUGIN_AUTH != 0 {
+            push_null_terminated(&mut packet, &plugin);
+        }
+        self.write_packet(&packet).await?;
+
+        loop {
+            let reply = self.read_packet().await?;
+            match reply.first() {
+                Some(0x00) => return Ok(()),
+                Some(0xff) => return Err(parse_error(&reply)),
+                // Auth switch request
+                Some(0xfe) => {
+                    let mut parts = reply[1..].splitn(2, |b| *b == 0);
+                    plugin = String::from_utf8_lossy(parts.next().unwrap_or_default()).to_string();
+                    scramble = parts.next().unwrap_or_default().to_vec();
+                    if scramble.last() == Some(&0) {
+                        scramble.pop();
+                    }
+                    let auth = scramble_password(&plugin, &login.password, &scramble)?;
+                    self.write_packet(&auth).await?;
+                }
+                // caching_sha2_password: the password was cached on the server
+                Some(0x01) if reply.get(1) == Some(&0x03) => {}
+                // caching_sha2_password wants the whole password, which
+                // without TLS has to be encrypted with the server's key
+                Some(0x01) if reply.get(1) == Some(&0x04) => {
+                    self.write_packet(&[0x02]).await?;
+                    let key = self.read_packet().await?;
+                    if key.first() == Some(&0xff) {
+                        return Err(parse_error(&key));
+                    }
+                    let encrypted = encrypt_password(&key[1..], &login.password, &scramble)?;
+                    self.write_packet(&encrypted).await?;
+                }
+                _ => return Err(format!("unexpected reply from {}", self.server)),
+            }
+        }
+    }
+    /// Runs a query and returns the rows it produced, if any.
+    pub async fn query(&mut self, query: &str) -> Result<Vec<Row>, String> {
+        self.sequence = 0;
+        let mut packet = vec![0x03];
+        packet.extend_from_slice(query.as_bytes());
+        self.write_packet(&packet).await?;
+        let reply = self.read_packet().await?;
+        let mut reader = Reader(&reply);
+        let columns = match reply.first() {
+            Some(0x00) => return Ok(Vec::new()),
+            Some(0xff) => return Err(parse_error(&reply)),
+            _ => reader.int().ok_or_else(|| self.invalid())?,
+        };
+        // Column definitions, then an EOF packet
+        for _ in 0..=columns {
+            self.read_packet().await?;
+        }
+        let mut rows = Vec::new();
+        loop {
+            let packet = self.read_packet().await?;
+            match packet.first() {
+                Some(0xfe) if packet.len() < 9 => return Ok(rows),
+                Some(0xff) => return Err(parse_error(&packet)),
+                _ => {}
+            }
+            let mut reader = Reader(&packet);
+            let row = (0..columns)
+                .map(|_| reader.value())
+                .collect::<Option<Row>>()
+                .ok_or_else(|| self.invalid())?;
+            rows.push(row);
+        }
+    }
+    pub async fn quit(&mut self) {
+        self.sequence = 0;
+        let _ = self.write_packet(&[0x01]).await;
+    }
+    fn invalid(&self) -> String {
+        format!("{} sent an invalid packet", self.server)
+    }
+    async fn read_packet(&mut self) -> Result<Vec<u8>, String> {
+        let mut header = [0; 4];
+        self.stream
+            .read_exact(&mut header)
+            .await
+            .map_err(|err| format!("failed to read from {}: {}", self.server, err))?;
+        let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+        if len >= MAX_PACKET {
+            return Err(format!("{} sent a packet that is too big", self.server));
+        }
+        self.sequence = header[3].wrapping_add(1);
+        let mut packet = vec![0; len];
+        self.stream
+            .read_exact(&mut packet)
+            .await
+            .map_err(|err| format!("failed to read from {}: {}", self.server, err))?;
+        Ok(packet)
+    }
+    async fn write_packet(&mut self, payload: &[u8]) -> Result<(), String> {
+        let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+        packet.push(self.sequence);
+        packet.extend_from_slice(payload);
+        self.sequence = self.sequence.wrapping_add(1);
+        self.stream
+            .write_all(&packet)
+            .await
+            .map_err(|err| format!("failed to write to {}: {}", self.server, err))
+    }
+}
+
+/// Reads the initial handshake packet. The outer None means it couldn't be
+/// parsed, the inner error is the server refusing the connection.
+fn parse_greeting(packet: &[u8]) -> Option<Result<Greeting, String>> {
+    if packet.first() == Some(&0xff) {
+        return Some(Err(parse_error(packet)));
+    }
+    let mut reader = Reader(packet);
+    if reader.bytes(1)?[0] != 10 {
+        return None;
+    }
+    reader.null_terminated()?;
+    reader.bytes(4)?;
+    let mut scramble = reader.bytes(8)?.to_vec();
+    reader.bytes(1)?;
+    let lower = reader.bytes(2)?;
+    let mut capabilities = u16::from_le_bytes([lower[0], lower[1]]) as u32;
+    let mut plugin = String::new();
+    // Character set and status flags, then the rest of the greeting for
+    // servers newer than 4.1
+    if reader.bytes(3).is_some() {
+        let upper = reader.bytes(2)?;
+        capabilities |= (u16::from_le_bytes([upper[0], upper[1]]) as u32) << 16;
+        let scramble_len = reader.bytes(1)?[0] as usize;
+        reader.bytes(10)?;
+        if capabilities & CLIENT_SECURE_CONNECTION != 0 {
+            let len = scramble_len.saturating_sub(8).max(13);
+            let part = reader.bytes(len)?;
+            scramble.extend_from_slice(part.strip_suffix(&[0]).unwrap_or(part));
+        }
+        if capabilities & CLIENT_PLUGIN_AUTH != 0 {
+            let name = reader.null_terminated().unwrap_or(reader.0);
+            plugin = String::from_utf8_lossy(name).to_string();
+        }
+    }
+    Some(Ok(Greeting {
+        capabilities,
+        scramble,
+        plugin,
+    }))
+}
+
+/// The message from an error packet
+fn parse_error(packet: &[u8]) -> String {
+    let mut message = packet.get(3..).unwrap_or_default();
+    if message.first() == Some(&b'#') {
+        message = message.get(6..).unwrap_or_default();
+    }
+    String::from_utf8_lossy(message).to_string()
+}
+
+fn push_null_terminated(packet: &mut Vec<u8>, value: &str) {
+    packet.extend_from_slice(value.as_bytes());
+    packet.push(0);
+}
+
+/// The first response to the server's challenge for an auth plugin.
+fn scramble_password(plugin: &str, password: &str, scramble: &[u8]) -> Result<Vec<u8>, String> {
+    if password.is_empty() {
+        return Ok(Vec::new());
+    }
+    match plugin {
+        // SHA1(password) XOR SHA1(scramble + SHA1(SHA1(password)))
+        NATIVE_PASSWORD => {
+            let hash = Sha1::digest(password);
+            let double = Sha1::digest(hash);
+            let mix = Sha1::new()
+                .chain_update(scramble)
+                .chain_update(double)
+                .finalize();
+            Ok(hash.iter().zip(mix).map(|(a, b)| a ^ b).collect())
+        }
+        // SHA256(password) XOR SHA256(SHA256(SHA256(password)) + scramble)
+        CACHING_SHA2_PASSWORD => {
+            let hash = Sha256::digest(password);
+            let double = Sha256::digest(hash);
+            let mix = Sha256::new()
+                .chain_update(double)
+                .chain_update(scramble)
+                .finalize();
+            Ok(hash.iter().zip(mix).map(|(a, b)| a ^ b).collect())
+        }
+        _ => Err(format!("unsupported authentication method {}", plugin)),
+    }
+}
+
+/// The password XORed with the scramble and encrypted with the server's
+/// RSA key, for logging in without TLS.
+fn encrypt_password(pem: &[u8], password: &str, scramble: &[u8]) -> Result<Vec<u8>, String> {
+    let pem = String::from_utf8_lossy(pem);
+    let key = RsaPublicKey::from_public_key_pem(pem.trim())
+        .map_err(|err| format!("invalid server key: {}", err))?;
+    let mut plain = password.as_bytes().to_vec();
+    plain.push(0);
+    for (i, byte) in plain.iter_mut().enumerate() {
+        *byte ^= scramble[i % scramble.len()];
+    }
+    key.encrypt(&mut rand::thread_rng(), Oaep::new::<Sha1>(), &plain)
+        .map_err(|err| format!("failed to encrypt password: {}", err))
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+    fn null_terminated(&mut self) -> Option<&'a [u8]> {
+        let end = self.0.iter().position(|b| *b == 0)?;
+        let value = self.bytes(end)?;
+        self.bytes(1)?;
+        Some(value)
+    }
+    /// A length encoded integer
+    fn int(&mut self) -> Option<u64> {
+        let len = match self.bytes(1)?[0] {
+            byte @ 0..=0xfa => return Some(byte as u64),
+            0xfc => 2,
+            0xfd => 3,
+            0xfe => 8,
+            _ => return None,
+        };
+        let mut value = [0; 8];
+        value[..len].copy_from_slice(self.bytes(len)?);
+        Some(u64::from_le_bytes(value))
+    }
+    /// A column value in a text result row
+    fn value(&mut self) -> Option<Option<String>> {
+        if self.0.first() == Some(&0xfb) {
+            self.bytes(1)?;
+            return Some(None);
+        }
+        let len = self.int()? as usize;
+        Some(Some(String::from_utf8_lossy(self.bytes(len)?).to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const SCRAMBLE: &[u8; 20] = b"abcdefghijklmnopqrst";
+    /// SHA1(SHA1("hunter2")), as a server stores it
+    const STORED: &str = "58815970be77b3720276f63db198b1fa42e5cc02";
+
+    /// Checks a mysql_native_password response the way a server does
+    fn verify(auth: &[u8]) -> bool {
+        let stored: Vec<u8> = (0..STORED.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&STORED[i..i + 2], 16).unwrap())
+            .collect();
+        let mix = Sha1::new()
+            .chain_update(SCRAMBLE)
+            .chain_update(&stored)
+            .finalize();
+        let hash: Vec<u8> = auth.iter().zip(mix).map(|(a, b)| a ^ b).collect();
+        Sha1::digest(hash).to_vec() == stored
+    }
+
+    async fn read(stream: &mut TcpStream) -> Option<Vec<u8>> {
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).await.ok()?;
+        let mut packet = vec![0; u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize];
+        stream.read_exact(&mut packet).await.ok()?;
+        Some(packet)
+    }
+
+    async fn send(stream: &mut TcpStream, sequence: u8, payload: &[u8]) {
+        let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+        packet.push(sequence);
+        packet.extend_from_slice(payload);
+        stream.write_all(&packet).await.unwrap();
+    }
+
+    fn text(value: &str) -> Vec<u8> {
+        [&[value.len() as u8], value.as_bytes()].concat()
+    }
+
+    /// A MySQL server that knows `app` with password `hunter2` and answers
+    /// any query with the rows `(widget, 3)` and `(gadget, NULL)`.
+    async fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let capabilities =
+                    CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION | CLIENT_PLUGIN_AUTH;
+                let mut greeting = vec![10];
+                greeting.extend_from_slice(b"8.0.36\0");
+                greeting.extend_from_slice(&7u32.to_le_bytes());
+                greeting.extend_from_slice(&SCRAMBLE[..8]);
+                greeting.push(0);
+                greeting.extend_from_slice(&(capabilities as u16).to_le_bytes());
+                greeting.extend_from_slice(&[45, 2, 0]);
+                greeting.extend_from_slice(&((capabilities >> 16) as u16).to_le_bytes());
+                greeting.push(21);
+                greeting.extend_from_slice(&[0; 10]);
+                greeting.extend_from_slice(&SCRAMBLE[8..]);
+                greeting.push(0);
+                greeting.extend_from_slice(b"mysql_native_password\0");
+                send(&mut stream, 0, &greeting).await;
+
+                let response = read(&mut stream).await.unwrap();
+                let mut reader = Reader(&response[32..]);
+                let user = reader.null_terminated().unwrap().to_vec();
+                let len = reader.bytes(1).unwrap()[0] as usize;
+                let auth = reader.bytes(len).unwrap();
+                if user != b"app" || !verify(auth) {
+                    let error = b"\xff\x15\x04#28000Access denied for user 'app'";
+                    send(&mut stream, 2, error).await;
+                    continue;
+                }
+                send(&mut stream, 2, &[0, 0, 0, 2, 0, 0, 0]).await;
+
+                while let Some(query) = read(&mut stream).await {
+                    if query[0] != 0x03 {
+                        break;
+                    }
+                    if !query.starts_with(b"\x03SELECT") {
+                        send(&mut stream, 1, &[0, 0, 0, 2, 0, 0, 0]).await;
+                        continue;
+                    }
+                    let eof = [0xfe, 0, 0, 2, 0];
+                    send(&mut stream, 1, &[2]).await;
+                    send(&mut stream, 2, &text("name")).await;
+                    send(&mut stream, 3, &text("count")).await;
+                    send(&mut stream, 4, &eof).await;
+                    send(&mut stream, 5, &[text("widget"), text("3")].concat()).await;
+                    send(&mut stream, 6, &[text("gadget"), vec![0xfb]].concat()).await;
+                    send(&mut stream, 7, &eof).await;
+                }
+            }
+        });
+        addr
+    }
+
+    fn login(password: &str) -> UserPass {
+        UserPass {
+            username: "app".to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn login_and_query() {
+        let server = serve().await;
+        let mut conn = Connection::connect(&server).await.unwrap();
+        conn.login(&login("hunter2"), None).await.unwrap();
+        assert_eq!(conn.query("START TRANSACTION READ ONLY").await, Ok(vec![]));
+        assert_eq!(
+            conn.query("SELECT name, count FROM products").await,
+            Ok(vec![
+                vec![Some("widget".to_string()), Some("3".to_string())],
+                vec![Some("gadget".to_string()), None],
+            ])
+        );
+        conn.quit().await;
+
+        let mut conn = Connection::connect(&server).await.unwrap();
+        assert_eq!(
+            conn.login(&login("wrong"), None).await,
+            Err("Access denied for user 'app'".to_string())
+        );
+    }
+}
@@ -0,0 +1,347 @@
+use std::{fmt, time::Duration};
+
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time::timeout,
+};
+
+use super::CheckResult;
+use crate::checker::env::substitute;
+
+/// A dialogue with a server: connect, send some bytes and expect a reply,
+/// as many times as needed. Addresses and sent text can use the team's
+/// variables.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ScriptCheck {
+    /// Run in order. The first step has to be a `connect`.
+    pub steps: Vec<Step>,
+    /// Seconds each step may take unless it sets its own timeout
+    #[serde(default = "default_step_timeout")]
+    pub step_timeout: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Step {
+    #[serde(flatten)]
+    pub action: Action,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Connect to a `host:port`, closing any earlier connection
+    Connect(String),
+    Send(String),
+    /// Send bytes written in hex like `fe 01`
+    SendHex(String),
+    /// Wait for the received data to match a regex
+    Expect(String),
+    /// Wait for bytes written in hex to be received
+    ExpectHex(String),
+}
+
+fn default_step_timeout() -> f64 {
+    2.0
+}
+
+/// Longest a step description gets in a failure reason
+const MAX_DESCRIPTION: usize = 40;
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, value) = match self {
+            Action::Connect(addr) => ("connect", addr.as_str()),
+            Action::Send(text) => ("send", text.as_str()),
+            Action::SendHex(hex) => ("send_hex", hex.as_str()),
+            Action::Expect(regex) => ("expect", regex.as_str()),
+            Action::ExpectHex(hex) => ("expect_hex", hex.as_str()),
+        };
+        let mut value = format!("{:?}", value);
+        if value.len() > MAX_DESCRIPTION {
+            value = value.chars().take(MAX_DESCRIPTION).collect::<String>() + "...";
+        }
+        write!(f, "{} {}", name, value)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+impl ScriptCheck {
+    pub fn is_valid(&self) -> bool {
+        matches!(
+            self.steps.first(),
+            Some(Step {
+                action: Action::Connect(_),
+                ..
+            })
+        ) && self.step_timeout.is_finite()
+            && self.step_timeout > 0.0
+            && self.steps.iter().all(|step| {
+                step.timeout.is_none_or(|t| t.is_finite() && t > 0.0)
+                    && match &step.action {
+                        Action::Connect(addr) => !addr.is_empty(),
+                        Action::Send(_) => true,
+                        Action::SendHex(hex) | Action::ExpectHex(hex) => parse_hex(hex).is_some(),
+                        Action::Expect(regex) => Regex::new(regex).is_ok(),
+                    }
+            })
+    }
+    pub async fn run(&self, transport: Transport, env: &[(String, String)]) -> CheckResult {
+        let mut session = Session {
+            transport,
+            connection: None,
+            received: Vec::new(),
+        };
+        for (number, step) in self.steps.iter().enumerate() {
+            let limit = Duration::from_secs_f64(step.timeout.unwrap_or(self.step_timeout));
+            let result = match timeout(limit, session.run(&step.action, env)).await {
+                Ok(result) => result,
+                Err(_) => Err(format!("timed out after {:?}", limit)),
+            };
+            if let Err(reason) = result {
+                let mut reason = format!("step {} ({}): {}", number + 1, step.action, reason);
+                if matches!(step.action, Action::Expect(_) | Action::ExpectHex(_)) {
+                    reason += &format!(", received {}", escape(&session.received));
+                }
+                return Err(reason);
+            }
+        }
+        Ok(format!("completed {} steps", self.steps.len()))
+    }
+}
+
+struct Session {
+    transport: Transport,
+    connection: Option<Connection>,
+    /// Data received but not yet matched by an expect step
+    received: Vec<u8>,
+}
+
+impl Session {
+    async fn run(&mut self, action: &Action, env: &[(String, String)]) -> Result<(), String> {
+        match action {
+            Action::Connect(addr) => self.connect(&substitute(addr, env)).await,
+            Action::Send(text) => self.send(substitute(text, env).as_bytes()).await,
+            Action::SendHex(hex) => self.send(&parse_hex(hex).ok_or("invalid hex")?).await,
+            Action::Expect(regex) => {
+                let regex = Regex::new(regex).map_err(|err| err.to_string())?;
+                self.expect(|data| regex.find(data).map(|m| m.end())).await
+            }
+            Action::ExpectHex(hex) => {
+                let bytes = parse_hex(hex).ok_or("invalid hex")?;
+                self.expect(|data| {
+                    data.windows(bytes.len())
+                        .position(|window| window == bytes)
+                        .map(|start| start + bytes.len())
+                })
+                .await
+            }
+        }
+    }
+    async fn connect(&mut self, addr: &str) -> Result<(), String> {
+        self.received.clear();
+        self.connection = None;
+        let connection = match self.transport {
+            Transport::Tcp => Connection::Tcp(
+                TcpStream::connect(addr)
+                    .await
+                    .map_err(|err| format!("failed to connect: {}", err))?,
+            ),
+            Transport::Udp => {
+                let local = if addr.starts_with('[') {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                };
+                let socket = UdpSocket::bind(local)
+                    .await
+                    .map_err(|err| format!("failed to open socket: {}", err))?;
+                socket
+                    .connect(addr)
+                    .await
+                    .map_err(|err| format!("failed to connect: {}", err))?;
+                Connection::Udp(socket)
+            }
+        };
+        self.connection = Some(connection);
+        Ok(())
+    }
+    async fn send(&mut self, data: &[u8]) -> Result<(), String> {
+        let result = match &mut self.connection {
+            Some(Connection::Tcp(stream)) => stream.write_all(data).await,
+            Some(Connection::Udp(socket)) => socket.send(data).await.map(|_| ()),
+            None => return Err("not connected".to_string()),
+        };
+        result.map_err(|err| format!("failed to send: {}", err))
+    }
+    /// Reads until `find` returns where the match ends, then drops the
+    /// received data up to there.
+    async fn expect(&mut self, find: impl Fn(&[u8]) -> Option<usize>) -> Result<(), String> {
+        loop {
+            if let Some(end) = find(&self.received) {
+                self.received.drain(..end);
+                return Ok(());
+            }
+            let mut buf = vec![0; 64 * 1024];
+            let read = match &mut self.connection {
+                Some(Connection::Tcp(stream)) => stream.read(&mut buf).await,
+                Some(Connection::Udp(socket)) => socket.recv(&mut buf).await,
+                None => return Err("not connected".to_string()),
+            };
+            match read {
+                Ok(0) if matches!(self.transport, Transport::Tcp) => {
+                    return Err("connection closed".to_string())
+                }
+                Ok(len) => self.received.extend_from_slice(&buf[..len]),
+                Err(err) => return Err(format!("failed to receive: {}", err)),
+            }
+        }
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Received data for a failure reason, with unprintable bytes escaped
+fn escape(data: &[u8]) -> String {
+    const MAX: usize = 100;
+    let mut escaped: String = data
+        .iter()
+        .take(MAX)
+        .flat_map(|b| std::ascii::escape_default(*b))
+        .map(char::from)
+        .collect();
+    if data.len() > MAX {
+        escaped += "...";
+    }
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Greets with a banner, then answers `hi <name>` with `hello <name>`
+    async fn serve_tcp() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    stream
+                        .get_mut()
+                        .write_all(b"\xfe\x01GAME v2\n")
+                        .await
+                        .unwrap();
+                    let mut line = String::new();
+                    while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+                        if let Some(name) = line.trim_end().strip_prefix("hi ") {
+                            let reply = format!("hello {}\n", name);
+                            stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    async fn serve_udp() -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+                let _ = socket.send_to(&buf[..len], from).await;
+            }
+        });
+        addr
+    }
+
+    fn script(yaml: &str) -> ScriptCheck {
+        let check: ScriptCheck = serde_yaml::from_str(yaml).unwrap();
+        assert!(check.is_valid());
+        check
+    }
+
+    #[tokio::test]
+    async fn run_dialogues() {
+        let env = vec![
+            ("GAME".to_string(), serve_tcp().await),
+            ("ECHO".to_string(), serve_udp().await),
+            ("TEAM".to_string(), "team1".to_string()),
+        ];
+        let hello = script(
+            "steps:
+                - connect: $GAME
+                - expect_hex: fe01
+                - expect: 'GAME v[0-9]+'
+                - send: \"hi $TEAM\\n\"
+                - expect: hello team1",
+        );
+        assert_eq!(
+            hello.run(Transport::Tcp, &env).await,
+            Ok("completed 5 steps".to_string())
+        );
+
+        let wrong = script(
+            "steps:
+                - connect: $GAME
+                - send: \"hi team2\\n\"
+                - expect: hello team1
+                  timeout: 0.2",
+        );
+        assert_eq!(
+            wrong.run(Transport::Tcp, &env).await,
+            Err("step 3 (expect \"hello team1\"): timed out after 200ms, received \"\\xfe\\x01GAME v2\\nhello team2\\n\"".to_string())
+        );
+
+        let echo = script("steps: [{connect: $ECHO}, {send_hex: 'de ad'}, {expect_hex: dead}]");
+        assert!(echo.run(Transport::Udp, &env).await.is_ok());
+
+        let refused = script("steps: [{connect: '127.0.0.1:1'}]");
+        assert!(refused
+            .run(Transport::Tcp, &env)
+            .await
+            .unwrap_err()
+            .starts_with("step 1 (connect \"127.0.0.1:1\"): failed to connect"));
+    }
+
+    #[test]
+    fn invalid_scripts() {
+        let not_connected: ScriptCheck = serde_yaml::from_str("steps: [{send: hi}]").unwrap();
+        assert!(!not_connected.is_valid());
+        let bad_hex: ScriptCheck =
+            serde_yaml::from_str("steps: [{connect: 'a:1'}, {send_hex: abc}]").unwrap();
+        assert!(!bad_hex.is_valid());
+    }
+}
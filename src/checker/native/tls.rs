@@ -0,0 +1,430 @@
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use x509_parser::{
+    certificate::X509Certificate, extensions::GeneralName, prelude::FromDer, public_key::PublicKey,
+};
+
+use super::{describe_error, with_default_port, CheckResult, OneOrMany};
+use crate::checker::env::substitute;
+
+/// Connects to a team's TLS server and checks the certificate it presents.
+/// Self-signed certificates are fine unless `trusted` is set.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TlsCheck {
+    /// Server to connect to, on port 443 unless another is given
+    pub server: String,
+    /// Name the certificate must be for. The server's host if not set.
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Days the certificate must still be valid for
+    #[serde(default)]
+    pub min_days: u32,
+    /// Smallest key allowed, in bits. EC keys are measured by their curve,
+    /// so P-256 is 256 bits.
+    #[serde(default)]
+    pub min_key_bits: Option<usize>,
+    /// Text the issuer's name must contain, like `CN=Team CA`
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// Hex SHA-256 fingerprints of the certificates allowed. Colons are
+    /// ignored, so `openssl x509 -fingerprint -sha256` output works.
+    #[serde(default)]
+    pub fingerprint: Option<OneOrMany<String>>,
+    /// Require a chain to a public certificate authority
+    #[serde(default)]
+    pub trusted: bool,
+}
+
+impl TlsCheck {
+    pub fn is_valid(&self) -> bool {
+        !self.server.is_empty()
+            && self.hostname.as_ref().is_none_or(|h| !h.is_empty())
+            && self.fingerprint.as_ref().is_none_or(|pins| {
+                pins.as_slice()
+                    .iter()
+                    .all(|pin| normalize_fingerprint(pin).len() == 64)
+            })
+    }
+    pub async fn run(&self, env: &[(String, String)]) -> CheckResult {
+        let server = with_default_port(&substitute(&self.server, env), 443);
+        let hostname = match &self.hostname {
+            Some(hostname) => substitute(hostname, env),
+            None => host(&server).to_string(),
+        };
+        let (der, handshake) = handshake(&server, &hostname, self.trusted).await?;
+        // Problems with the certificate itself say more than the handshake
+        // failing because of them, like webpki refusing small RSA keys
+        let message = self.check_certificate(&der, &hostname, env)?;
+        handshake.map(|_| message)
+    }
+    fn check_certificate(
+        &self,
+        der: &[u8],
+        hostname: &str,
+        env: &[(String, String)],
+    ) -> CheckResult {
+        let (_, cert) = X509Certificate::from_der(der)
+            .map_err(|err| format!("invalid certificate: {}", err))?;
+        if !matches_name(&cert, hostname) {
+            return Err(format!(
+                "certificate is for {}, not {}",
+                names(&cert).join(", "),
+                hostname
+            ));
+        }
+        let days_left = days_left(&cert)?;
+        if days_left < self.min_days as i64 {
+            return Err(format!(
+                "certificate expires in {} days on {}, expected at least {}",
+                days_left,
+                cert.validity().not_after,
+                self.min_days
+            ));
+        }
+        if let Some(min_bits) = self.min_key_bits {
+            let key = cert
+                .public_key()
+                .parsed()
+                .map_err(|err| format!("invalid public key: {}", err))?;
+            if key.key_size() < min_bits {
+                return Err(format!(
+                    "{} key is {} bits, expected at least {}",
+                    key_kind(&key),
+                    key.key_size(),
+                    min_bits
+                ));
+            }
+        }
+        let issuer = cert.issuer().to_string();
+        if let Some(expected) = &self.issuer {
+            let expected = substitute(expected, env);
+            if !issuer.contains(&expected) {
+                return Err(format!("issued by {}, expected {}", issuer, expected));
+            }
+        }
+        if let Some(pins) = &self.fingerprint {
+            let fingerprint = format!("{:x}", Sha256::digest(der));
+            if !pins
+                .as_slice()
+                .iter()
+                .any(|pin| normalize_fingerprint(pin) == fingerprint)
+            {
+                return Err(format!(
+                    "certificate fingerprint {} is not pinned",
+                    fingerprint
+                ));
+            }
+        }
+        Ok(format!(
+            "certificate for {} from {} valid for {} more days",
+            hostname, issuer, days_left
+        ))
+    }
+}
+
+/// Does the handshake and returns the server's certificate, along with
+/// whether the handshake went through.
+async fn handshake(
+    server: &str,
+    hostname: &str,
+    trusted: bool,
+) -> Result<(Vec<u8>, Result<(), String>), String> {
+    let name = ServerName::try_from(hostname.trim_end_matches('.'))
+        .map_err(|_| format!("invalid hostname {}", hostname))?;
+    let verifier = Arc::new(Verifier::new(trusted));
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+    let stream = TcpStream::connect(server)
+        .await
+        .map_err(|err| format!("failed to connect to {}: {}", server, err))?;
+    let result = TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await
+        .map(|_| ())
+        .map_err(|err| {
+            format!(
+                "TLS handshake with {} failed: {}",
+                server,
+                describe_error(&err)
+            )
+        });
+    let certificate = verifier.seen.lock().unwrap().take();
+    match certificate {
+        Some(certificate) => Ok((certificate, result)),
+        None => Err(result
+            .err()
+            .unwrap_or_else(|| format!("{} sent no certificate", server))),
+    }
+}
+
+/// Keeps the server's certificate so it can be checked after the
+/// handshake. Any certificate is accepted unless it has to chain to a
+/// public certificate authority.
+struct Verifier {
+    webpki: Option<WebPkiVerifier>,
+    seen: Mutex<Option<Vec<u8>>>,
+}
+
+impl Verifier {
+    fn new(trusted: bool) -> Self {
+        let webpki = trusted.then(|| {
+            let mut roots = RootCertStore::empty();
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }));
+            WebPkiVerifier::new(roots, None)
+        });
+        Verifier {
+            webpki,
+            seen: Mutex::new(None),
+        }
+    }
+}
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        *self.seen.lock().unwrap() = Some(end_entity.0.clone());
+        match &self.webpki {
+            Some(verifier) => verifier.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            ),
+            None => Ok(ServerCertVerified::assertion()),
+        }
+    }
+}
+
+/// The host part of a `host:port` address
+fn host(server: &str) -> &str {
+    let host = server.rsplit_once(':').map_or(server, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// The DNS names and addresses in the certificate, or its common name if
+/// it has none.
+fn names(cert: &X509Certificate) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(dns) => names.push(dns.to_string()),
+                GeneralName::IPAddress(bytes) => {
+                    let ip = match bytes.len() {
+                        4 => <[u8; 4]>::try_from(*bytes).map(IpAddr::from).ok(),
+                        16 => <[u8; 16]>::try_from(*bytes).map(IpAddr::from).ok(),
+                        _ => None,
+                    };
+                    names.extend(ip.map(|ip| ip.to_string()));
+                }
+                _ => {}
+            }
+        }
+    }
+    if names.is_empty() {
+        names.extend(
+            cert.subject()
+                .iter_common_name()
+                .filter_map(|cn| cn.as_str().ok())
+                .map(str::to_string),
+        );
+    }
+    names
+}
+
+fn matches_name(cert: &X509Certificate, hostname: &str) -> bool {
+    let hostname = hostname.trim_end_matches('.').to_ascii_lowercase();
+    names(cert).iter().any(|name| {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        if let (Ok(a), Ok(b)) = (name.parse::<IpAddr>(), hostname.parse::<IpAddr>()) {
+            return a == b;
+        }
+        match name.strip_prefix("*.") {
+            // A wildcard only stands for one label
+            Some(domain) => hostname
+                .split_once('.')
+                .is_some_and(|(label, rest)| !label.is_empty() && rest == domain),
+            None => name == hostname,
+        }
+    })
+}
+
+/// Whole days until the certificate expires, failing if it isn't valid now.
+fn days_left(cert: &X509Certificate) -> Result<i64, String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let validity = cert.validity();
+    if now < validity.not_before.timestamp() {
+        return Err(format!(
+            "certificate is not valid until {}",
+            validity.not_before
+        ));
+    }
+    if now > validity.not_after.timestamp() {
+        return Err(format!("certificate expired on {}", validity.not_after));
+    }
+    Ok((validity.not_after.timestamp() - now) / (24 * 60 * 60))
+}
+
+fn key_kind(key: &PublicKey) -> &'static str {
+    match key {
+        PublicKey::RSA(_) => "RSA",
+        PublicKey::EC(_) => "EC",
+        PublicKey::DSA(_) => "DSA",
+        _ => "public",
+    }
+}
+
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{CertificateParams, DistinguishedName, DnType};
+    use rustls::{PrivateKey, ServerConfig};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::*;
+
+    /// Serves a self-signed certificate for localhost that expires in
+    /// 30 days, returning the address and the certificate.
+    async fn serve_tls() -> (String, Vec<u8>) {
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]);
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Team CA");
+        params.not_after = time::OffsetDateTime::now_utc() + time::Duration::hours(30 * 24 + 1);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        let der = cert.serialize_der().unwrap();
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(der.clone())],
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let _ = acceptor.accept(stream).await;
+                });
+            }
+        });
+        (addr, der)
+    }
+
+    #[tokio::test]
+    async fn check_certificates() {
+        let (server, der) = serve_tls().await;
+        let env = vec![("WEB".to_string(), server)];
+        let run = |yaml: String| {
+            let env = env.clone();
+            async move {
+                let check: TlsCheck = serde_yaml::from_str(&yaml).unwrap();
+                assert!(check.is_valid());
+                check.run(&env).await
+            }
+        };
+        let fingerprint = format!("{:X}", Sha256::digest(&der));
+        assert_eq!(
+            run(format!(
+                "{{server: $WEB, hostname: localhost, min_days: 7, min_key_bits: 256, issuer: Team CA, fingerprint: '{}'}}",
+                fingerprint
+            ))
+            .await,
+            Ok("certificate for localhost from CN=Team CA valid for 30 more days".to_string())
+        );
+        assert_eq!(
+            run("{server: $WEB}".to_string()).await,
+            Err("certificate is for localhost, not 127.0.0.1".to_string())
+        );
+        let expiring = run("{server: $WEB, hostname: localhost, min_days: 60}".to_string())
+            .await
+            .unwrap_err();
+        assert!(
+            expiring.starts_with("certificate expires in 30 days on "),
+            "{}",
+            expiring
+        );
+        assert_eq!(
+            run("{server: $WEB, hostname: localhost, min_key_bits: 2048}".to_string()).await,
+            Err("EC key is 256 bits, expected at least 2048".to_string())
+        );
+        assert_eq!(
+            run("{server: $WEB, hostname: localhost, issuer: Let's Encrypt}".to_string()).await,
+            Err("issued by CN=Team CA, expected Let's Encrypt".to_string())
+        );
+        let pinned = run(format!(
+            "{{server: $WEB, hostname: localhost, fingerprint: [{}]}}",
+            "00".repeat(32)
+        ))
+        .await
+        .unwrap_err();
+        assert!(pinned.ends_with("is not pinned"), "{}", pinned);
+        let untrusted = run("{server: $WEB, hostname: localhost, trusted: true}".to_string())
+            .await
+            .unwrap_err();
+        assert!(untrusted.contains("UnknownIssuer"), "{}", untrusted);
+    }
+
+    #[test]
+    fn wildcard_names() {
+        let mut params =
+            CertificateParams::new(vec!["*.team1.local".to_string(), "10.0.0.1".to_string()]);
+        params.subject_alt_names[1] = rcgen::SanType::IpAddress("10.0.0.1".parse().unwrap());
+        let der = rcgen::Certificate::from_params(params)
+            .unwrap()
+            .serialize_der()
+            .unwrap();
+        let (_, cert) = X509Certificate::from_der(&der).unwrap();
+        assert!(matches_name(&cert, "www.team1.local"));
+        assert!(matches_name(&cert, "WWW.team1.local."));
+        assert!(matches_name(&cert, "10.0.0.1"));
+        assert!(!matches_name(&cert, "team1.local"));
+        assert!(!matches_name(&cert, "a.www.team1.local"));
+        assert!(!matches_name(&cert, "10.0.0.2"));
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, RwLock},
+};
+
+use serde::Serialize;
+
+use super::service::CheckStatus;
+
+/// What a checker printed during one scheduled check.
+#[derive(Serialize, Clone, Debug)]
+pub struct CheckOutput {
+    pub tick: u64,
+    /// Unix time in milliseconds when the check finished
+    pub time: u128,
+    pub status: CheckStatus,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Recent outputs keyed by (team, service), newest first.
+type OutputRings = BTreeMap<(String, String), VecDeque<CheckOutput>>;
+
+/// The most recent checker outputs for every team and service.
+///
+/// Like the check history, clones share the same buffers. Outputs are only
+/// meant for debugging during the game so they are not saved.
+#[derive(Clone, Default)]
+pub struct OutputLog {
+    outputs: Arc<RwLock<OutputRings>>,
+}
+
+/// How many outputs to keep per team and service. Set with SB_OUTPUT_HISTORY.
+fn output_history_len() -> usize {
+    std::env::var("SB_OUTPUT_HISTORY")
+        .ok()
+        .and_then(|len| len.parse().ok())
+        .unwrap_or(10)
+}
+
+impl OutputLog {
+    pub fn push(&self, team: &str, service: &str, output: CheckOutput) {
+        let Ok(mut outputs) = self.outputs.write() else {
+            return;
+        };
+        let ring = outputs
+            .entry((team.to_string(), service.to_string()))
+            .or_default();
+        ring.push_front(output);
+        ring.truncate(output_history_len());
+    }
+    /// Recent outputs, newest first, optionally filtered by team and service.
+    pub fn get(&self, team: Option<&str>, service: Option<&str>) -> Vec<ServiceOutputs> {
+        let Ok(outputs) = self.outputs.read() else {
+            return vec![];
+        };
+        outputs
+            .iter()
+            .filter(|((t, s), _)| {
+                team.is_none_or(|team| team == t) && service.is_none_or(|service| service == s)
+            })
+            .map(|((team, service), ring)| ServiceOutputs {
+                team: team.clone(),
+                service: service.clone(),
+                outputs: ring.iter().cloned().collect(),
+            })
+            .collect()
+    }
+    /// The error from the latest check of a service if it was down or timed
+    /// out, cleaned up so it can be shown to the team.
+    pub fn last_error(
+        &self,
+        team: &str,
+        service: &str,
+        env: &[(String, String)],
+    ) -> Option<String> {
+        let outputs = self.outputs.read().ok()?;
+        let latest = outputs
+            .get(&(team.to_string(), service.to_string()))?
+            .front()?;
+        if !matches!(latest.status, CheckStatus::Down | CheckStatus::Timeout) {
+            return None;
+        }
+        let error = if latest.stderr.trim().is_empty() {
+            &latest.stdout
+        } else {
+            &latest.stderr
+        };
+        Some(sanitize_error(error, env))
+    }
+}
+
+#[derive(Serialize)]
+pub struct ServiceOutputs {
+    pub team: String,
+    pub service: String,
+    pub outputs: Vec<CheckOutput>,
+}
+
+const MAX_ERROR_LEN: usize = 200;
+const SECRET_NAMES: [&str; 5] = ["PASS", "SECRET", "TOKEN", "KEY", "CRED"];
+
+/// Reduces checker output to its last non-empty line, hides the values of
+/// secret looking environment variables, removes control characters and
+/// caps the length.
+pub fn sanitize_error(error: &str, env: &[(String, String)]) -> String {
+    let mut line = error
+        .lines()
+        .rev()
+        .find(|l| !l.trim().is_empty())
+        .unwrap_or("")
+        .trim()
+        .to_string();
+    for (name, value) in env {
+        let name = name.to_uppercase();
+        if !value.is_empty() && SECRET_NAMES.iter().any(|s| name.contains(s)) {
+            line = line.replace(value, "****");
+        }
+    }
+    let mut line: String = line.chars().filter(|c| !c.is_control()).collect();
+    if line.chars().count() > MAX_ERROR_LEN {
+        line = line.chars().take(MAX_ERROR_LEN).collect::<String>() + "...";
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn sanitize_hides_secrets() {
+        let env = vec![
+            ("SSH_PASSWORD".to_string(), "hunter2".to_string()),
+            ("HOST".to_string(), "10.0.0.1".to_string()),
+        ];
+        let error = "connecting\nlogin to 10.0.0.1 with hunter2 failed\x07\n\n";
+        assert_eq!(
+            sanitize_error(error, &env),
+            "login to 10.0.0.1 with **** failed"
+        );
+    }
+}
@@ -1,5 +1,6 @@
-use std::{str::FromStr, collections::BTreeMap};
+use std::{str::FromStr, collections::BTreeMap, fmt::Display, sync::RwLock};
 
+use rand::Rng;
 use serde::{Serialize, Deserialize};
 use tracing::{error,info};
 
@@ -7,14 +8,14 @@ use crate::checker::Config;
 
 use super::resource_location;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UserPass {
     pub username: String,
     pub password: String,
 }
-impl ToString for UserPass {
-    fn to_string(&self) -> String {
-        format!("{}:{}", self.username, self.password)
+impl Display for UserPass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.username, self.password)
     }
 }
 
@@ -39,6 +40,32 @@ impl FromStr for UserPass {
     }
 }
 
+/// Password groups a service's checker gets a login from.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Credentials {
+    pub group: String,
+    #[serde(default)]
+    pub pick: Pick,
+}
+
+/// How a login is picked from a password group for each check.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Pick {
+    /// A random login every check
+    #[default]
+    Random,
+    /// The login after the one used last time, in file order
+    RoundRobin,
+    /// Every login in turn in the same check, which only passes if all of
+    /// them work
+    All,
+}
+
+/// Held while reading or writing password files so checkers never see a
+/// file a team is halfway through changing.
+static PASSWORD_LOCK: RwLock<()> = RwLock::new(());
+
 #[derive(Serialize, Deserialize)]
 pub struct PasswordSave {
     pub group: String,
@@ -71,6 +98,7 @@ pub fn load_password_saves(save: &BTreeMap<String,Vec<PasswordSave>>) {
 pub enum PasswordError {
     InvalidFile,
     ParseError,
+    NoSuchUser,
 }
 
 pub fn validate_password_fs(config: &Config) {
@@ -79,7 +107,7 @@ pub fn validate_password_fs(config: &Config) {
         std::fs::create_dir(&path).unwrap();
     }
     for (team,_) in config.teams.iter() {
-        path.push(&team);
+        path.push(team);
         if !path.exists() {
             std::fs::create_dir(&path).unwrap();
         }
@@ -90,15 +118,13 @@ pub fn validate_password_fs(config: &Config) {
         return;
     };
     // loop through path, if team doesn't exist, remove it
-    for entry in read_dir {
-        if let Ok(entry) = entry {
-            if let Some(filename) = entry.file_name().to_str() {
-                if !config.teams.contains_key(filename) {
-                    if let Err(err) = std::fs::remove_dir_all(entry.path()) {
-                        error!("Error removing directory: {}", err);
-                    } else {
-                        info!("Removed directory of nonexistent team: {}", team_password_dir(&filename.to_string()));
-                    }
+    for entry in read_dir.flatten() {
+        if let Some(filename) = entry.file_name().to_str() {
+            if !config.teams.contains_key(filename) {
+                if let Err(err) = std::fs::remove_dir_all(entry.path()) {
+                    error!("Error removing directory: {}", err);
+                } else {
+                    info!("Removed directory of nonexistent team: {}", team_password_dir(&filename.to_string()));
                 }
             }
         }
@@ -106,6 +132,7 @@ pub fn validate_password_fs(config: &Config) {
 }
 
 pub fn remove_password_group(team_name: &String, group: &String) -> Result<(), PasswordError> {
+    let _lock = PASSWORD_LOCK.write().unwrap_or_else(|e| e.into_inner());
     let path = format!("{}/{}.pw", team_password_dir(team_name), group);
     std::fs::remove_file(path).map_err(|_| PasswordError::InvalidFile)?;
     Ok(())
@@ -115,13 +142,11 @@ pub fn get_password_groups(team_name: &String) -> Result<Vec<String>, PasswordEr
     let path = team_password_dir(team_name);
     let mut groups = Vec::new();
     if let Ok(dir) = std::fs::read_dir(path) {
-        for entry in dir {
-            if let Ok(entry) = entry {
-                if let Some(filename) = entry.file_name().to_str() {
-                    if filename.ends_with(".pw") {
-                        // remove .pw
-                        groups.push(filename[..filename.len() - 3].to_string());
-                    }
+        for entry in dir.flatten() {
+            if let Some(filename) = entry.file_name().to_str() {
+                // remove .pw
+                if let Some(group) = filename.strip_suffix(".pw") {
+                    groups.push(group.to_string());
                 }
             }
         }
@@ -132,6 +157,7 @@ pub fn get_password_groups(team_name: &String) -> Result<Vec<String>, PasswordEr
 }
 
 pub fn get_passwords(team_name: &String, group: &String) -> Result<String, PasswordError> {
+    let _lock = PASSWORD_LOCK.read().unwrap_or_else(|e| e.into_inner());
     let path = format!("{}/{}.pw", team_password_dir(team_name), group);
     let contents = std::fs::read_to_string(path).map_err(|_| PasswordError::InvalidFile)?;
     Ok(contents)
@@ -140,6 +166,14 @@ pub fn get_passwords(team_name: &String, group: &String) -> Result<String, Passw
 fn read_passwords(
     team_name: &String,
     group: &String,
+) -> Result<Vec<UserPass>, PasswordError> {
+    let _lock = PASSWORD_LOCK.read().unwrap_or_else(|e| e.into_inner());
+    read_passwords_unlocked(team_name, group)
+}
+
+fn read_passwords_unlocked(
+    team_name: &String,
+    group: &String,
 ) -> Result<Vec<UserPass>, PasswordError> {
     // Read the file at resources/PW/<team_name>/<password_file>.pw
     let path = format!("{}/{}.pw", team_password_dir(team_name), group);
@@ -148,38 +182,98 @@ fn read_passwords(
     Ok(passwords)
 }
 
+/// A login from one of a team's password groups for checkers to use. Picks
+/// a random one unless a username is given.
+pub fn pick_password(
+    team_name: &String,
+    group: &String,
+    username: Option<&str>,
+) -> Result<UserPass, PasswordError> {
+    let mut passwords = read_passwords(team_name, group)?;
+    let index = match username {
+        Some(username) => passwords.iter().position(|p| p.username == username),
+        None if passwords.is_empty() => None,
+        None => Some(rand::thread_rng().gen_range(0..passwords.len())),
+    };
+    let index = index.ok_or(PasswordError::NoSuchUser)?;
+    Ok(passwords.swap_remove(index))
+}
+
+/// The logins a checker should use for one check, in the order to try
+/// them. `last` is the username used by the previous check.
+pub fn pick_logins(
+    team_name: &String,
+    credentials: &Credentials,
+    last: Option<&str>,
+) -> Result<Vec<UserPass>, PasswordError> {
+    let passwords = read_passwords(team_name, &credentials.group)?;
+    choose_logins(passwords, credentials.pick, last)
+}
+
+fn choose_logins(
+    mut passwords: Vec<UserPass>,
+    pick: Pick,
+    last: Option<&str>,
+) -> Result<Vec<UserPass>, PasswordError> {
+    if passwords.is_empty() {
+        return Err(PasswordError::NoSuchUser);
+    }
+    let index = match pick {
+        Pick::All => return Ok(passwords),
+        Pick::Random => rand::thread_rng().gen_range(0..passwords.len()),
+        Pick::RoundRobin => last
+            .and_then(|last| passwords.iter().position(|p| p.username == last))
+            .map_or(0, |index| (index + 1) % passwords.len()),
+    };
+    Ok(vec![passwords.swap_remove(index)])
+}
+
+/// Replaces a password file without readers ever seeing part of it.
+fn replace_password_file(path: &str, contents: String) -> Result<(), PasswordError> {
+    let temp = format!("{}.tmp", path);
+    std::fs::write(&temp, contents).map_err(|_| PasswordError::InvalidFile)?;
+    std::fs::rename(&temp, path).map_err(|_| PasswordError::InvalidFile)
+}
+
+/// Makes the password directory for a new team.
+pub fn create_team_password_dir(team_name: &String) {
+    if let Err(err) = std::fs::create_dir_all(team_password_dir(team_name)) {
+        error!("Error creating password directory for {}: {}", team_name, err);
+    }
+}
+
 pub fn write_passwords(
     team_name: &String,
     group: &String,
-    passwords: &String,
+    passwords: &str,
 ) -> Result<(), PasswordError> {
     let path = format!("{}/{}.pw", team_password_dir(team_name), group);
     // I know this looks stupid.
     // But we want to parse the passwords to make sure they are valid before we write them to the file.
-    let contents = passwords_to_string(&parse_passwords(&passwords));
-    std::fs::write(path, contents).map_err(|_| PasswordError::InvalidFile)?;
-    Ok(())
+    let contents = passwords_to_string(&parse_passwords(passwords));
+    let _lock = PASSWORD_LOCK.write().unwrap_or_else(|e| e.into_inner());
+    replace_password_file(&path, contents)
 }
 
 pub fn overwrite_passwords(
     team_name: &String,
     group: &String,
-    passwords: &String,
+    passwords: &str,
 ) -> Result<(), PasswordError> {
     let path = format!("{}/{}.pw", team_password_dir(team_name), group);
-    let mut old_passwords = read_passwords(team_name, group)?;
+    let _lock = PASSWORD_LOCK.write().unwrap_or_else(|e| e.into_inner());
+    let mut old_passwords = read_passwords_unlocked(team_name, group)?;
     for password in parse_passwords(passwords) {
         if let Some(index) = old_passwords.iter().position(|p| p.username == password.username) {
             old_passwords[index] = password;
         }
     };
-    std::fs::write(path,passwords_to_string(&old_passwords)).map_err(|_| PasswordError::InvalidFile)?;
-    Ok(())
+    replace_password_file(&path, passwords_to_string(&old_passwords))
 }
 
 /// Parses a string of the form "username:password" into a UserPass struct.
 /// Returns an error if any of the strings are not valid.
-fn parse_passwords(password_string: &String) -> Vec<UserPass> {
+fn parse_passwords(password_string: &str) -> Vec<UserPass> {
     let passwords = password_string.split_whitespace().filter_map(|s| s.parse().ok()).collect();
     passwords
 }
@@ -188,7 +282,7 @@ fn passwords_to_string(passwords: &Vec<UserPass>) -> String {
     let mut password_string = String::new();
     for password in passwords {
         password_string.push_str(&password.to_string());
-        password_string.push_str("\n");
+        password_string.push('\n');
     }
     password_string
 }
@@ -200,3 +294,30 @@ fn password_dir() -> String {
 fn team_password_dir(team: &String) -> String {
     format!("{}/{}",password_dir(),team)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn choose_logins_in_turn() {
+        let passwords = parse_passwords("alice:a1 bob:b2 carol:c3");
+        let usernames = |pick, last| -> Vec<String> {
+            choose_logins(passwords.clone(), pick, last)
+                .unwrap()
+                .into_iter()
+                .map(|login| login.username)
+                .collect()
+        };
+        assert_eq!(usernames(Pick::RoundRobin, None), ["alice"]);
+        assert_eq!(usernames(Pick::RoundRobin, Some("bob")), ["carol"]);
+        assert_eq!(usernames(Pick::RoundRobin, Some("carol")), ["alice"]);
+        // A user that was removed starts over
+        assert_eq!(usernames(Pick::RoundRobin, Some("dave")), ["alice"]);
+        assert_eq!(usernames(Pick::All, None), ["alice", "bob", "carol"]);
+        assert_eq!(usernames(Pick::Random, None).len(), 1);
+        assert!(matches!(
+            choose_logins(vec![], Pick::Random, None),
+            Err(PasswordError::NoSuchUser)
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use super::service::CheckStatus;
+
+/// Environment variable with the path of the file a checker writes its
+/// report to.
+pub const REPORT_FILE_VAR: &str = "SB_RESULT_FILE";
+
+/// A structured result a checker writes as a JSON object to the file at
+/// `$SB_RESULT_FILE`, like `{"status": "degraded", "fraction": 0.5}`. It
+/// takes precedence over the exit code. Only the file counts, so nothing a
+/// checker prints from the service it checks can stand in for a report.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct CheckReport {
+    pub status: CheckStatus,
+    /// Share of the service's points earned, from 0 to 1
+    pub fraction: Option<f64>,
+    pub message: Option<String>,
+    #[serde(default)]
+    pub metrics: BTreeMap<String, f64>,
+}
+
+impl CheckReport {
+    /// Reads the report file's contents. An empty file means there is no
+    /// report.
+    pub fn parse(contents: &str) -> Result<Option<Self>, String> {
+        if contents.trim().is_empty() {
+            return Ok(None);
+        }
+        serde_json::from_str(contents.trim())
+            .map(Some)
+            .map_err(|err| format!("Invalid report: {}", err))
+    }
+    /// Share of the points earned, defaulting to all of them when up, half
+    /// when degraded and none otherwise.
+    pub fn fraction(&self) -> f64 {
+        let fraction = self.fraction.unwrap_or_else(|| self.status.default_fraction());
+        if fraction.is_finite() {
+            fraction.clamp(0.0, 1.0)
+        } else {
+            self.status.default_fraction()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parse_report() {
+        let contents = "{\"status\": \"degraded\", \"fraction\": 0.75, \"message\": \"login broken\", \"metrics\": {\"latency\": 12.5}}\n";
+        let report = CheckReport::parse(contents).unwrap().unwrap();
+        assert_eq!(report.status, CheckStatus::Degraded);
+        assert_eq!(report.fraction(), 0.75);
+        assert_eq!(report.message.as_deref(), Some("login broken"));
+        assert_eq!(report.metrics.get("latency"), Some(&12.5));
+        let report = CheckReport::parse("{\"status\": \"up\"}").unwrap().unwrap();
+        assert_eq!(report.fraction(), 1.0);
+    }
+    #[test]
+    fn empty_or_invalid_report() {
+        assert_eq!(CheckReport::parse(" \n"), Ok(None));
+        assert!(CheckReport::parse("Server is running: hello").is_err());
+        assert!(CheckReport::parse("{\"status\": \"ok\"}").is_err());
+    }
+}
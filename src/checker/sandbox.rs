@@ -0,0 +1,314 @@
+use std::{
+    ffi::CString,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{chown, OpenOptionsExt},
+        process::CommandExt,
+    },
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Restrictions applied to a checker process before it starts. Any setting
+/// left out of a service falls back to the global one from the SB_SANDBOX_*
+/// environment variables.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Sandbox {
+    /// User name or uid to run as. Needs the scoreboard to run as root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Group name or gid to run as. Defaults to the user's primary group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Seconds of CPU time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<u64>,
+    /// Megabytes of address space
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_files: Option<u64>,
+    /// Processes owned by the checker's user, so only useful with `user`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processes: Option<u64>,
+    /// Mount the resources directory read only for the checker. Needs the
+    /// scoreboard to run as root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+}
+
+fn env_setting<T: std::str::FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
+impl Sandbox {
+    /// Settings for every checker, read once from the environment.
+    pub fn global() -> &'static Sandbox {
+        static GLOBAL: OnceLock<Sandbox> = OnceLock::new();
+        GLOBAL.get_or_init(|| Sandbox {
+            user: env_setting("SB_SANDBOX_USER"),
+            group: env_setting("SB_SANDBOX_GROUP"),
+            cpu: env_setting("SB_SANDBOX_CPU"),
+            memory: env_setting("SB_SANDBOX_MEMORY"),
+            open_files: env_setting("SB_SANDBOX_FILES"),
+            processes: env_setting("SB_SANDBOX_PROCESSES"),
+            read_only: env_setting("SB_SANDBOX_READ_ONLY"),
+        })
+    }
+    /// These settings with the blanks filled in from `other`.
+    pub fn or(&self, other: &Sandbox) -> Sandbox {
+        Sandbox {
+            user: self.user.clone().or_else(|| other.user.clone()),
+            group: self.group.clone().or_else(|| other.group.clone()),
+            cpu: self.cpu.or(other.cpu),
+            memory: self.memory.or(other.memory),
+            open_files: self.open_files.or(other.open_files),
+            processes: self.processes.or(other.processes),
+            read_only: self.read_only.or(other.read_only),
+        }
+    }
+    pub fn is_valid(&self) -> bool {
+        self.user.as_ref().is_none_or(|u| !u.is_empty())
+            && self.group.as_ref().is_none_or(|g| !g.is_empty())
+            && [self.cpu, self.memory, self.open_files, self.processes]
+                .iter()
+                .all(|limit| limit.is_none_or(|limit| limit > 0))
+    }
+    /// The uid and gid the checker runs as, if they are changed.
+    pub fn ids(&self) -> io::Result<(Option<libc::uid_t>, Option<libc::gid_t>)> {
+        let ids = match &self.user {
+            Some(user) => Some(lookup_user(user)?),
+            None => None,
+        };
+        let gid = match &self.group {
+            Some(group) => Some(lookup_group(group)?),
+            None => ids.map(|(_, gid)| gid),
+        };
+        Ok((ids.map(|(uid, _)| uid), gid))
+    }
+    /// Writes a temporary file that only the checker's user can read and
+    /// write, for handing the checker things like its store.
+    pub fn create_file(&self, prefix: &str, contents: &[u8]) -> io::Result<SandboxFile> {
+        let (uid, gid) = self.ids()?;
+        let path = std::env::temp_dir().join(format!("{}-{}", prefix, Uuid::new_v4()));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        let sandbox_file = SandboxFile { path };
+        file.write_all(contents)?;
+        if uid.is_some() || gid.is_some() {
+            chown(&sandbox_file.path, uid, gid)?;
+        }
+        Ok(sandbox_file)
+    }
+    /// Sets up `command` to drop into the sandbox between fork and exec.
+    /// Users and groups are looked up here since that isn't safe to do in
+    /// the child.
+    pub fn apply(&self, command: &mut Command, resource_dir: &Path) -> io::Result<()> {
+        let (uid, gid) = self.ids()?;
+        let read_only = match self.read_only {
+            Some(true) => {
+                let dir = resource_dir.canonicalize()?;
+                Some(CString::new(dir.as_os_str().as_bytes())?)
+            }
+            _ => None,
+        };
+        let limits = [
+            (libc::RLIMIT_CPU, self.cpu),
+            (libc::RLIMIT_AS, self.memory.map(|mb| mb.saturating_mul(1024 * 1024))),
+            (libc::RLIMIT_NOFILE, self.open_files),
+            (libc::RLIMIT_NPROC, self.processes),
+        ];
+        // SAFETY: the closure only makes async-signal-safe libc calls on
+        // values prepared above and doesn't allocate.
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(dir) = &read_only {
+                    mount_read_only(dir)?;
+                }
+                for (resource, limit) in limits {
+                    if let Some(limit) = limit {
+                        let limit = libc::rlimit {
+                            rlim_cur: limit as libc::rlim_t,
+                            rlim_max: limit as libc::rlim_t,
+                        };
+                        check(libc::setrlimit(resource, &limit))?;
+                    }
+                }
+                if let Some(gid) = gid {
+                    check(libc::setgroups(0, std::ptr::null()))?;
+                    check(libc::setgid(gid))?;
+                }
+                if let Some(uid) = uid {
+                    check(libc::setuid(uid))?;
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A file made for one run of a checker. It is removed when dropped.
+pub struct SandboxFile {
+    path: PathBuf,
+}
+
+impl SandboxFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn read_to_string(&self) -> io::Result<String> {
+        fs::read_to_string(&self.path)
+    }
+}
+
+impl Drop for SandboxFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn check(res: libc::c_int) -> io::Result<()> {
+    if res == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Puts the child in its own mount namespace and bind mounts `dir` over
+/// itself read only. The working directory is entered again so it points
+/// at the new mount.
+unsafe fn mount_read_only(dir: &CString) -> io::Result<()> {
+    let null = std::ptr::null();
+    check(libc::unshare(libc::CLONE_NEWNS))?;
+    // Keep the new mounts from propagating back to the host
+    check(libc::mount(
+        null,
+        c"/".as_ptr(),
+        null,
+        libc::MS_REC | libc::MS_PRIVATE,
+        std::ptr::null(),
+    ))?;
+    check(libc::mount(
+        dir.as_ptr(),
+        dir.as_ptr(),
+        null,
+        libc::MS_BIND | libc::MS_REC,
+        std::ptr::null(),
+    ))?;
+    check(libc::mount(
+        null,
+        dir.as_ptr(),
+        null,
+        libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+        std::ptr::null(),
+    ))?;
+    check(libc::chdir(dir.as_ptr()))
+}
+
+fn lookup_user(user: &str) -> io::Result<(libc::uid_t, libc::gid_t)> {
+    let name = CString::new(user)?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: passwd is plain old data and getpwnam_r/getpwuid_r only write
+    // into it and buf, whose real length is passed along.
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let res = unsafe {
+        match user.parse::<libc::uid_t>() {
+            Ok(uid) => libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result),
+            Err(_) => libc::getpwnam_r(
+                name.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            ),
+        }
+    };
+    if result.is_null() {
+        if let Ok(uid) = user.parse() {
+            // A bare uid without a passwd entry keeps the current group
+            // SAFETY: getgid can't fail
+            return Ok((uid, unsafe { libc::getgid() }));
+        }
+        return Err(lookup_error("user", user, res));
+    }
+    Ok((pwd.pw_uid, pwd.pw_gid))
+}
+
+fn lookup_group(group: &str) -> io::Result<libc::gid_t> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = CString::new(group)?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: as in lookup_user
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let res = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if result.is_null() {
+        return Err(lookup_error("group", group, res));
+    }
+    Ok(grp.gr_gid)
+}
+
+fn lookup_error(kind: &str, name: &str, res: libc::c_int) -> io::Error {
+    if res != 0 {
+        return io::Error::from_raw_os_error(res);
+    }
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no such {}: {}", kind, name),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn limits_apply_to_child() {
+        let sandbox = Sandbox {
+            open_files: Some(64),
+            cpu: Some(7),
+            ..Default::default()
+        };
+        let mut command = Command::new("bash");
+        command.arg("-c").arg("ulimit -n; ulimit -t");
+        sandbox.apply(&mut command, Path::new(".")).unwrap();
+        let output = command.output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "64\n7\n");
+    }
+    #[test]
+    fn service_settings_win() {
+        let global = Sandbox {
+            user: Some("nobody".to_string()),
+            memory: Some(512),
+            ..Default::default()
+        };
+        let service = Sandbox {
+            memory: Some(128),
+            ..Default::default()
+        };
+        let merged = service.or(&global);
+        assert_eq!(merged.user.as_deref(), Some("nobody"));
+        assert_eq!(merged.memory, Some(128));
+    }
+}
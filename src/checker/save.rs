@@ -17,6 +17,7 @@ pub struct Save {
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum SaveError {
     ReadError,
     ParseError,
@@ -30,13 +31,13 @@ pub fn save_config(config: &Config, file_name: &str) -> Result<(), SaveError> {
     };
     let passwords = config
         .teams
-        .iter()
-        .filter_map(|(name, _)| {
-            if let Ok(groups) = get_password_groups(&name) {
+        .keys()
+        .filter_map(|name| {
+            if let Ok(groups) = get_password_groups(name) {
                 let saves = groups
                     .iter()
                     .filter_map(|group| {
-                        if let Ok(passwords) = get_passwords(&name, &group) {
+                        if let Ok(passwords) = get_passwords(name, group) {
                             Some(PasswordSave {
                                 group: group.clone(),
                                 passwords,
@@ -91,14 +92,12 @@ pub fn load_save(file_name: &str) -> Result<Save, SaveError> {
 pub fn get_save_names() -> Vec<String> {
     let mut names = Vec::new();
     if let Ok(entries) = fs::read_dir(format!("{}/save",resource_location())) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if let Ok(file_type) = entry.file_type() {
-                    if file_type.is_file() {
-                        if let Some(name) = entry.file_name().to_str() {
-                            if name.ends_with(".json") {
-                                names.push(name[0..name.len() - 5].to_string());
-                            }
+        for entry in entries.flatten() {
+            if let Ok(file_type) = entry.file_type() {
+                if file_type.is_file() {
+                    if let Some(name) = entry.file_name().to_str() {
+                        if name.ends_with(".json") {
+                            names.push(name[0..name.len() - 5].to_string());
                         }
                     }
                 }
@@ -111,14 +110,12 @@ pub fn get_save_names() -> Vec<String> {
 pub fn get_autosave_names() -> Vec<String> {
     let mut names = Vec::new();
     if let Ok(entries) = fs::read_dir(format!("{}/save/autosave",resource_location())) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if let Ok(file_type) = entry.file_type() {
-                    if file_type.is_file() {
-                        if let Some(name) = entry.file_name().to_str() {
-                            if name.ends_with(".json") {
-                                names.push(name[0..name.len() - 5].to_string());
-                            }
+        for entry in entries.flatten() {
+            if let Ok(file_type) = entry.file_type() {
+                if file_type.is_file() {
+                    if let Some(name) = entry.file_name().to_str() {
+                        if name.ends_with(".json") {
+                            names.push(name[0..name.len() - 5].to_string());
                         }
                     }
                 }
@@ -1,105 +1,1081 @@
 use std::{
-    collections::BTreeMap, fs, time::Duration
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant},
 };
 
-use tokio::{process::Command, time::timeout};
-
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use tracing::debug;
 
-use super::resource_location;
+use super::{
+    context::{CheckContext, CONTEXT_FILE_VAR},
+    env::{is_reserved_name, is_valid_name, substitute},
+    executor::{executor, ProcessOutput},
+    native::{team_login, Baseline, ContentSource, IntegrityCheck, NativeCheck, OneOrMany},
+    password::{pick_logins, Credentials, PasswordError, UserPass},
+    report::{CheckReport, REPORT_FILE_VAR},
+    resource_location,
+    sandbox::{Sandbox, SandboxFile},
+    store::{format_store, parse_store, Store, STORE_FILE_VAR},
+    tick_duration,
+};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Service {
+    /// Taken from the key when loaded from services.yaml
+    #[serde(default)]
     pub name: String,
+    /// Bash command for the checker. Empty for native checks.
+    #[serde(default)]
     pub command: String,
-    pub multiplier: u8,
+    /// A check run by the scoreboard itself instead of `command`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check: Option<NativeCheck>,
+    /// Program and arguments to run instead of `command`, without a shell.
+    /// `$VAR` and `${VAR}` in them are replaced with the team's values, and
+    /// each stays a single argument whatever the value holds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec: Option<Vec<String>>,
+    /// What runs the checker. bash for `command` and the program itself for
+    /// `exec` if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interpreter: Option<Interpreter>,
+    /// Points awarded to a team for every tick this service is up.
+    #[serde(default = "default_multiplier", alias = "points")]
+    pub multiplier: f64,
+    /// Penalty policy for consecutive downtime. No penalties if None.
+    #[serde(default)]
+    pub sla: Option<Sla>,
+    /// Show teams a sanitized version of the last error when this service
+    /// is down.
+    #[serde(default)]
+    pub show_errors: bool,
+    /// Seconds a single attempt may run before it counts as down
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default)]
+    pub schedule: Schedule,
+    /// How many more times to try a failing check before marking it down
+    #[serde(default)]
+    pub retries: u32,
+    /// Host this service checks, like `$HOST`. Checks against the same host
+    /// share a concurrency limit.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Password groups to give the checker logins from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<OneOrMany<Credentials>>,
+    /// Variables for this service only. They win over the team's and the
+    /// global ones, and can use `$VAR` from them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Limits for the checker process on top of the global ones
+    #[serde(default)]
+    pub sandbox: Sandbox,
+}
+
+/// How often a service is checked.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Schedule {
+    #[default]
+    EveryTick,
+    /// Every Nth tick
+    Ticks(u32),
+    /// Roughly every this many seconds, rounded up to whole ticks
+    Seconds(u64),
+}
+
+impl Schedule {
+    fn is_valid(&self) -> bool {
+        match self {
+            Schedule::EveryTick => true,
+            Schedule::Ticks(ticks) => *ticks > 0,
+            Schedule::Seconds(seconds) => *seconds > 0,
+        }
+    }
+    /// Number of ticks between checks
+    pub fn period(&self) -> u64 {
+        match self {
+            Schedule::EveryTick => 1,
+            Schedule::Ticks(ticks) => (*ticks).max(1) as u64,
+            Schedule::Seconds(seconds) => {
+                let tick = tick_duration().as_secs().max(1);
+                seconds.div_ceil(tick).max(1)
+            }
+        }
+    }
+    /// Whether the service should be checked on this tick. Ticks start
+    /// at 1 so every service is checked on the first tick of the game.
+    pub fn is_due(&self, tick: u64) -> bool {
+        tick.saturating_sub(1).is_multiple_of(self.period())
+    }
+}
+
+/// What runs a service's checker. With `command` it is given the command
+/// to run, and with `exec` it runs the program as a script.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpreter {
+    Bash,
+    Sh,
+    Python,
+    /// The program itself with no interpreter. Only works with `exec`.
+    Exec,
+}
+
+impl Interpreter {
+    fn program(&self) -> Option<&'static str> {
+        match self {
+            Interpreter::Bash => Some("bash"),
+            Interpreter::Sh => Some("sh"),
+            Interpreter::Python => Some("python3"),
+            Interpreter::Exec => None,
+        }
+    }
+}
+
+/// Deducts points from a team when a service has been down for
+/// `threshold` checks in a row.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Sla {
+    pub threshold: u32,
+    pub penalty: f64,
+    /// Apply the penalty again every `threshold` consecutive downs instead
+    /// of only once per outage.
+    #[serde(default)]
+    pub repeat: bool,
+}
+
+impl Sla {
+    pub fn is_valid(&self) -> bool {
+        self.threshold > 0 && self.penalty.is_finite() && self.penalty >= 0.0
+    }
+    /// Whether a service that has been down `down_streak` checks in a row
+    /// should be penalized on this check.
+    pub fn is_violated(&self, down_streak: u32) -> bool {
+        if self.threshold == 0 || down_streak == 0 {
+            return false;
+        }
+        if self.repeat {
+            down_streak.is_multiple_of(self.threshold)
+        } else {
+            down_streak == self.threshold
+        }
+    }
 }
 
 impl Service {
-    pub fn new(name: String, command: String, multiplier: u8) -> Self {
+    pub fn new(name: String, command: String, multiplier: f64) -> Self {
         Service {
             name,
             command,
+            check: None,
+            exec: None,
+            interpreter: None,
             multiplier,
+            sla: None,
+            show_errors: false,
+            timeout: default_timeout(),
+            schedule: Schedule::default(),
+            retries: 0,
+            host: None,
+            credentials: None,
+            env: BTreeMap::new(),
+            sandbox: Sandbox::default(),
         }
     }
     pub fn is_valid(&self) -> bool {
-        self.name != "" && self.command != ""
+        let checkers = [
+            !self.command.is_empty(),
+            self.exec.is_some(),
+            self.check.is_some(),
+        ];
+        !self.name.is_empty()
+            && checkers.iter().filter(|set| **set).count() == 1
+            && self
+                .exec
+                .as_ref()
+                .is_none_or(|exec| exec.first().is_some_and(|program| !program.is_empty()))
+            && match self.interpreter {
+                Some(Interpreter::Exec) => self.exec.is_some(),
+                Some(_) => self.check.is_none(),
+                None => true,
+            }
+            && self.credentials.as_ref().is_none_or(|credentials| {
+                let groups = credentials.as_slice();
+                groups.iter().all(|credentials| !credentials.group.is_empty())
+                    && self.check.as_ref().is_none_or(|check| {
+                        // Native checks log in once, with one login
+                        check.logs_in() && check.own_credentials().is_none() && groups.len() == 1
+                    })
+            })
+            && self.check.as_ref().is_none_or(|check| {
+                !check.needs_login()
+                    || check.own_credentials().is_some()
+                    || self.credentials.is_some()
+            })
+            && self
+                .env
+                .keys()
+                .all(|name| is_valid_name(name) && !is_reserved_name(name))
+            && self.check.as_ref().is_none_or(|check| check.is_valid())
+            && self.multiplier.is_finite()
+            && self.multiplier >= 0.0
+            && self.sla.as_ref().is_none_or(|sla| sla.is_valid())
+            && self.timeout > 0
+            // Every attempt has to fit in the ticks until the next check
+            && (self.retries as u64 + 1)
+                .checked_mul(self.timeout)
+                .is_some_and(|total| {
+                    total <= self.schedule.period().saturating_mul(tick_duration().as_secs())
+                })
+            && self.schedule.is_valid()
+            && self.sandbox.is_valid()
     }
-    #[tracing::instrument]
-    pub async fn check_with_env(&self, env: &Vec<(String, String)>) -> Result<TestOutput, ()> {
-        // get PATH from env
+    /// A random delay of up to `jitter` before starting the check, cut short
+    /// so the first attempt still has time to finish within the tick.
+    pub fn start_delay(&self, jitter: Duration, tick: Duration) -> Duration {
+        let latest = jitter.min(tick.saturating_sub(Duration::from_secs(self.timeout)));
+        if latest.is_zero() {
+            return Duration::ZERO;
+        }
+        rand::thread_rng().gen_range(Duration::ZERO..=latest)
+    }
+    /// Runs a native check once for each login picked for it, stopping at
+    /// the first one that isn't up.
+    async fn run_native(
+        &self,
+        check: &NativeCheck,
+        team: &str,
+        env: &[(String, String)],
+        last_logins: &BTreeMap<String, String>,
+    ) -> TestOutput {
+        let start = Instant::now();
+        let runs = match self.logins(team, last_logins) {
+            Ok(runs) => runs,
+            Err(reason) => {
+                return TestOutput::failed(CheckStatus::CheckerError, reason, start.elapsed())
+            }
+        };
+        let mut tried = BTreeMap::new();
+        let mut output = None;
+        for logins in &runs {
+            let login = logins.first().map(|(_, login)| login);
+            let run = self.run_native_once(check, team, env, login).await;
+            add_tried(&mut tried, logins);
+            let done = run.status != CheckStatus::Up;
+            output = Some(run);
+            if done {
+                break;
+            }
+        }
+        let mut output = output.expect("there is always at least one run");
+        output.logins = join_tried(tried);
+        output.duration = start.elapsed();
+        output
+    }
+    async fn run_native_once(
+        &self,
+        check: &NativeCheck,
+        team: &str,
+        env: &[(String, String)],
+        login: Option<&UserPass>,
+    ) -> TestOutput {
+        let start = Instant::now();
+        let host = self.host.as_ref().map(|host| substitute(host, env));
+        let limit = Duration::from_secs(self.timeout);
+        let result = executor()
+            .run_native(host.as_deref(), check.run(team, env, login, limit), limit)
+            .await;
+        let output = match result {
+            Some(Ok(message)) => TestOutput::passed(message, start.elapsed()),
+            Some(Err(reason)) => TestOutput::failed(CheckStatus::Down, reason, start.elapsed()),
+            None => TestOutput::failed(CheckStatus::Timeout, "timeout".to_string(), start.elapsed()),
+        };
+        debug!(
+            "{} is {:?}. {}{}",
+            self.name, output.status, output.message, output.error
+        );
+        output
+    }
+    /// The program and arguments that run the checker
+    fn argv(&self, env: &[(String, String)]) -> Vec<String> {
+        let Some(exec) = &self.exec else {
+            let shell = self.interpreter.and_then(|i| i.program()).unwrap_or("bash");
+            return vec![shell.to_string(), "-c".to_string(), self.command.clone()];
+        };
+        let args = exec.iter().map(|arg| substitute(arg, env));
+        match self.interpreter.and_then(|i| i.program()) {
+            Some(interpreter) => std::iter::once(interpreter.to_string()).chain(args).collect(),
+            None => args.collect(),
+        }
+    }
+    /// Runs a program in the resources directory like a checker, sandboxed
+    /// and with the team's variables.
+    async fn run_process(
+        &self,
+        argv: &[String],
+        env: &[(String, String)],
+    ) -> Result<ProcessOutput, TestOutput> {
         let resource_dir = resource_location();
         let path = std::env::var("PATH").unwrap_or("/usr/bin:/bin:/usr/sbin:/sbin".to_string());
-        let output = Command::new("bash")
-            .current_dir(resource_dir)
-            .arg("-c")
-            .arg(&self.command)
+        let start = Instant::now();
+        let Some((program, args)) = argv.split_first() else {
+            return Err(TestOutput::failed(
+                CheckStatus::CheckerError,
+                "Nothing to run".to_string(),
+                start.elapsed(),
+            ));
+        };
+        // Relative paths to scripts are from the resources directory, but
+        // which directory Command resolves them from isn't defined
+        let mut program = PathBuf::from(program);
+        if program.is_relative() && program.components().count() > 1 {
+            if let Ok(dir) = fs::canonicalize(&resource_dir) {
+                program = dir.join(program);
+            }
+        }
+        let mut command = Command::new(program);
+        command
+            .current_dir(&resource_dir)
+            .args(args)
             .env_clear()
             .env("PATH", path)
-            .envs(env.clone())
-            .output();
-        let Ok(res) = timeout(Duration::from_secs(5), output).await else {
-            debug!("{} timed out", self.name);
-            return Ok(TestOutput {
-                up: false,
-                message: "".to_string(),
-                error: "timeout".to_string(),
-            });
+            .envs(env.iter().cloned());
+        let sandbox = self.sandbox.or(Sandbox::global());
+        if let Err(err) = sandbox.apply(&mut command, Path::new(&resource_dir)) {
+            debug!("{} could not be sandboxed: {}", self.name, err);
+            return Err(TestOutput::failed(
+                CheckStatus::CheckerError,
+                format!("Failed to sandbox checker: {}", err),
+                start.elapsed(),
+            ));
+        }
+        let host = self.host.as_ref().map(|host| substitute(host, env));
+        let limit = Duration::from_secs(self.timeout);
+        executor().run(host.as_deref(), command, limit).await.map_err(|err| {
+            debug!("{} could not be run: {}", self.name, err);
+            TestOutput::failed(
+                CheckStatus::CheckerError,
+                format!("Failed to run checker: {}", err),
+                start.elapsed(),
+            )
+        })
+    }
+    /// Gets the content an integrity check watches, failing with the
+    /// output the check should have if it can't.
+    async fn integrity_content(
+        &self,
+        check: &IntegrityCheck,
+        team: &str,
+        env: &[(String, String)],
+    ) -> Result<Vec<u8>, TestOutput> {
+        let start = Instant::now();
+        if let ContentSource::Command(script) = &check.source {
+            let argv = ["bash".to_string(), "-c".to_string(), script.clone()];
+            let res = self.run_process(&argv, env).await?;
+            if res.timed_out {
+                return Err(TestOutput::failed(
+                    CheckStatus::Timeout,
+                    "timeout".to_string(),
+                    start.elapsed(),
+                ));
+            }
+            let code = res.status.and_then(|status| status.code());
+            if code != Some(0) {
+                let status = CheckStatus::from_exit_code(code);
+                let error = String::from_utf8_lossy(&res.stderr).to_string();
+                let mut output = TestOutput::failed(status, error, start.elapsed());
+                output.exit_code = code;
+                return Err(output);
+            }
+            return Ok(res.stdout);
+        }
+        let host = self.host.as_ref().map(|host| substitute(host, env));
+        let limit = Duration::from_secs(self.timeout);
+        let result = executor()
+            .run_native(host.as_deref(), check.fetch(team, env, limit), limit)
+            .await;
+        match result {
+            Some(Ok(content)) => Ok(content),
+            Some(Err(reason)) => Err(TestOutput::failed(CheckStatus::Down, reason, start.elapsed())),
+            None => Err(TestOutput::failed(CheckStatus::Timeout, "timeout".to_string(), start.elapsed())),
+        }
+    }
+    /// Compares a team's content to their baseline, taking the baseline if
+    /// they don't have one yet.
+    async fn run_integrity(
+        &self,
+        check: &IntegrityCheck,
+        team: &str,
+        env: &[(String, String)],
+        baseline: Option<&Baseline>,
+    ) -> TestOutput {
+        let start = Instant::now();
+        let content = match self.integrity_content(check, team, env).await {
+            Ok(content) => content,
+            Err(output) => return output,
+        };
+        let snapshot = check.snapshot(&content);
+        let Some(baseline) = baseline else {
+            let message = format!("took baseline sha256 {}", snapshot.hash);
+            let mut output = TestOutput::passed(message, start.elapsed());
+            output.baseline = Some(snapshot);
+            return output;
+        };
+        match check.compare(&snapshot, baseline) {
+            Ok(()) => {
+                TestOutput::passed("content matches the baseline".to_string(), start.elapsed())
+            }
+            Err((status, reason)) => {
+                let mut output = TestOutput::failed(status, reason, start.elapsed());
+                output.fraction = status.default_fraction();
+                output
+            }
+        }
+    }
+    pub fn is_integrity_check(&self) -> bool {
+        matches!(self.check, Some(NativeCheck::Integrity(_)))
+    }
+    /// Takes a new baseline of a team's content for an integrity check.
+    pub async fn take_baseline(&self, context: &CheckContext) -> Result<Baseline, String> {
+        let Some(NativeCheck::Integrity(check)) = &self.check else {
+            return Err(format!("{} is not an integrity check", self.name));
         };
-        let Ok(res) = res else {
-            return Err(());
+        let env = context.env();
+        match self.integrity_content(check, &context.team, &env).await {
+            Ok(content) => Ok(check.snapshot(&content)),
+            Err(output) => Err(match output.status {
+                CheckStatus::Timeout => "timeout".to_string(),
+                _ => output.error,
+            }),
+        }
+    }
+    /// Runs the check, trying again up to `retries` times while it is not up.
+    /// Integrity checks compare against `baseline`, and checkers start with
+    /// the values in `store`. Changes to the store carry over to retries.
+    /// Round robin logins continue from `last_logins`.
+    #[tracing::instrument]
+    pub async fn check_with_env(
+        &self,
+        context: &CheckContext,
+        baseline: Option<&Baseline>,
+        store: &Store,
+        last_logins: &BTreeMap<String, String>,
+    ) -> TestOutput {
+        let start = Instant::now();
+        let env = context.env();
+        let mut attempt = 0;
+        let mut updated: Option<Store> = None;
+        loop {
+            let current = updated.as_ref().unwrap_or(store);
+            let mut output = self
+                .run_once(context, &env, baseline, current, last_logins)
+                .await;
+            match &output.store {
+                Some(store) => updated = Some(store.clone()),
+                None => output.store = updated.clone(),
+            }
+            if output.status.is_up() || attempt >= self.retries {
+                output.duration = start.elapsed();
+                return output;
+            }
+            attempt += 1;
+            debug!(
+                "{} is {:?}, retry {} of {}",
+                self.name, output.status, attempt, self.retries
+            );
+        }
+    }
+    /// Writes the files a checker gets, its store, its context and an empty
+    /// report, so the checker's user can read and write them.
+    fn checker_files(
+        &self,
+        context: &CheckContext,
+        store: &Store,
+    ) -> io::Result<[SandboxFile; 3]> {
+        let sandbox = self.sandbox.or(Sandbox::global());
+        let store_file = sandbox.create_file("sb-store", format_store(store).as_bytes())?;
+        let context = serde_json::to_vec_pretty(context).map_err(io::Error::other)?;
+        let context_file = sandbox.create_file("sb-context", &context)?;
+        let report_file = sandbox.create_file("sb-result", &[])?;
+        Ok([store_file, context_file, report_file])
+    }
+    async fn run_once(
+        &self,
+        context: &CheckContext,
+        env: &[(String, String)],
+        baseline: Option<&Baseline>,
+        store: &Store,
+        last_logins: &BTreeMap<String, String>,
+    ) -> TestOutput {
+        let team = context.team.as_str();
+        match &self.check {
+            Some(NativeCheck::Integrity(check)) => {
+                return self.run_integrity(check, team, env, baseline).await
+            }
+            Some(check) => return self.run_native(check, team, env, last_logins).await,
+            None => {}
+        }
+        let start = Instant::now();
+        let runs = match self.logins(team, last_logins) {
+            Ok(runs) => runs,
+            Err(reason) => {
+                return TestOutput::failed(CheckStatus::CheckerError, reason, start.elapsed())
+            }
         };
+        let mut tried = BTreeMap::new();
+        let mut updated: Option<Store> = None;
+        let mut output = None;
+        for logins in &runs {
+            let current = updated.as_ref().unwrap_or(store);
+            let mut run = self.run_checker(context, env, logins, current).await;
+            match &run.store {
+                Some(store) => updated = Some(store.clone()),
+                None => run.store = updated.clone(),
+            }
+            add_tried(&mut tried, logins);
+            // Every login has to work, so there is no point going on
+            let done = run.status != CheckStatus::Up;
+            output = Some(run);
+            if done {
+                break;
+            }
+        }
+        let mut output = output.expect("there is always at least one run");
+        output.logins = join_tried(tried);
+        output.duration = start.elapsed();
+        output
+    }
+    /// The logins for each run of the checker in one check, by password
+    /// group. Only groups picked with `all` make more than one run. A
+    /// native check that names its own password group gets one login from it.
+    fn logins(
+        &self,
+        team: &str,
+        last: &BTreeMap<String, String>,
+    ) -> Result<Vec<Vec<(String, UserPass)>>, String> {
+        if let Some((group, user)) = self.check.as_ref().and_then(|c| c.own_credentials()) {
+            return Ok(vec![vec![(group.clone(), team_login(team, group, user)?)]]);
+        }
+        let mut runs = vec![vec![]];
+        let Some(credentials) = &self.credentials else {
+            return Ok(runs);
+        };
+        for credentials in credentials.as_slice() {
+            let group = &credentials.group;
+            let last = last.get(group).map(String::as_str);
+            let logins = pick_logins(&team.to_string(), credentials, last).map_err(|err| match err {
+                PasswordError::NoSuchUser => format!("password group {} is empty", group),
+                _ => format!("{} has no password group {}", team, group),
+            })?;
+            runs = runs
+                .into_iter()
+                .flat_map(|run| {
+                    logins.iter().map(move |login| {
+                        let mut run = run.clone();
+                        run.push((group.clone(), login.clone()));
+                        run
+                    })
+                })
+                .collect();
+        }
+        Ok(runs)
+    }
+    /// Runs the checker process once with the given logins.
+    async fn run_checker(
+        &self,
+        context: &CheckContext,
+        env: &[(String, String)],
+        logins: &[(String, UserPass)],
+        store: &Store,
+    ) -> TestOutput {
+        let start = Instant::now();
+        let [store_file, context_file, report_file] = match self.checker_files(context, store) {
+            Ok(files) => files,
+            Err(err) => {
+                debug!("{} could not create its files: {}", self.name, err);
+                return TestOutput::failed(
+                    CheckStatus::CheckerError,
+                    format!("Failed to create checker files: {}", err),
+                    start.elapsed(),
+                );
+            }
+        };
+        let mut env = env.to_vec();
+        env.push((
+            STORE_FILE_VAR.to_string(),
+            store_file.path().to_string_lossy().to_string(),
+        ));
+        env.push((
+            CONTEXT_FILE_VAR.to_string(),
+            context_file.path().to_string_lossy().to_string(),
+        ));
+        env.push((
+            REPORT_FILE_VAR.to_string(),
+            report_file.path().to_string_lossy().to_string(),
+        ));
+        env.extend(login_env(logins));
+        let res = match self.run_process(&self.argv(&env), &env).await {
+            Ok(res) => res,
+            Err(output) => return output,
+        };
+        let stdout = String::from_utf8_lossy(&res.stdout).to_string();
+        // A checker that removed its file keeps the values it started with
+        let updated = match store_file.read_to_string() {
+            Ok(contents) => parse_store(&contents),
+            Err(_) => store.clone(),
+        };
+        let updated = (updated != *store).then_some(updated);
+        if res.timed_out {
+            debug!("{} timed out", self.name);
+            // Keep whatever it printed before being killed for debugging
+            let mut output =
+                TestOutput::failed(CheckStatus::Timeout, "timeout".to_string(), start.elapsed());
+            output.message = stdout;
+            output.store = updated;
+            return output;
+        }
+        let mut output = TestOutput::from_process(
+            stdout,
+            String::from_utf8_lossy(&res.stderr).to_string(),
+            res.status.and_then(|status| status.code()),
+            &report_file.read_to_string().unwrap_or_default(),
+            start.elapsed(),
+        );
+        output.store = updated;
         debug!(
-            "{} is {}. stdout:{} stderr:{}",
-            self.name,
-            if res.status.success() { "UP" } else { "DOWN" },
-            String::from_utf8_lossy(&res.stdout),
-            String::from_utf8_lossy(&res.stderr)
+            "{} is {:?}. stdout:{} stderr:{}",
+            self.name, output.status, output.message, output.error
         );
-        Ok(TestOutput {
-            up: res.status.success(),
-            message: String::from_utf8_lossy(&res.stdout).to_string(),
-            error: String::from_utf8_lossy(&res.stderr).to_string(),
-        })
+        output
+    }
+}
+
+/// Records the usernames used in one run, by password group.
+fn add_tried(tried: &mut BTreeMap<String, Vec<String>>, logins: &[(String, UserPass)]) {
+    for (group, login) in logins {
+        let users = tried.entry(group.clone()).or_default();
+        if !users.contains(&login.username) {
+            users.push(login.username.clone());
+        }
+    }
+}
+
+/// The usernames tried in a check as they are kept in its output
+fn join_tried(tried: BTreeMap<String, Vec<String>>) -> BTreeMap<String, String> {
+    tried
+        .into_iter()
+        .map(|(group, users)| (group, users.join(", ")))
+        .collect()
+}
+
+/// Variables with a checker's logins. Each group gets `SB_<GROUP>_USER`,
+/// `SB_<GROUP>_PASSWORD` and `SB_<GROUP>_LOGIN` (`user:password`), and the
+/// first group is also in `SB_USER`, `SB_PASSWORD` and `SB_LOGIN`.
+fn login_env(logins: &[(String, UserPass)]) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    for (index, (group, login)) in logins.iter().enumerate() {
+        let group: String = group
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        let mut prefixes = vec![format!("SB_{}_", group)];
+        if index == 0 {
+            prefixes.push("SB_".to_string());
+        }
+        for prefix in prefixes {
+            env.push((format!("{}USER", prefix), login.username.clone()));
+            env.push((format!("{}PASSWORD", prefix), login.password.clone()));
+            env.push((format!("{}LOGIN", prefix), login.to_string()));
+        }
+    }
+    env
+}
+
+/// Exit codes that mean the checker itself is broken rather than the
+/// service being down. 3 is reserved for checkers to report their own
+/// problems. 126 and 127 are what bash returns when a command can't be
+/// executed or found.
+pub const CHECKER_ERROR_CODES: [i32; 3] = [3, 126, 127];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Up,
+    /// Up, but not everything the checker tested worked. Earns part of the
+    /// service's points.
+    Degraded,
+    Down,
+    Timeout,
+    /// The checker failed to run or reported that it is broken. This is not
+    /// the team's fault so it doesn't affect their score.
+    CheckerError,
+}
+
+impl CheckStatus {
+    pub fn from_exit_code(code: Option<i32>) -> Self {
+        match code {
+            Some(0) => CheckStatus::Up,
+            Some(code) if CHECKER_ERROR_CODES.contains(&code) => CheckStatus::CheckerError,
+            _ => CheckStatus::Down,
+        }
+    }
+    /// Whether the service is reachable, even if degraded
+    pub fn is_up(&self) -> bool {
+        matches!(self, CheckStatus::Up | CheckStatus::Degraded)
+    }
+    pub fn default_fraction(&self) -> f64 {
+        match self {
+            CheckStatus::Up => 1.0,
+            CheckStatus::Degraded => 0.5,
+            _ => 0.0,
+        }
     }
 }
 
 pub struct TestOutput {
-    pub up: bool,
+    pub status: CheckStatus,
+    /// Share of the service's points earned, from 0 to 1
+    pub fraction: f64,
+    /// stdout of the checker
     pub message: String,
+    /// stderr of the checker
     pub error: String,
+    /// Message from the checker's structured report
+    pub summary: Option<String>,
+    pub metrics: BTreeMap<String, f64>,
+    pub exit_code: Option<i32>,
+    pub duration: Duration,
+    /// Baseline taken by an integrity check for a team that had none
+    pub baseline: Option<Baseline>,
+    /// Values the checker stored, if it changed them
+    pub store: Option<Store>,
+    /// Usernames the checker was given, by password group
+    pub logins: BTreeMap<String, String>,
 }
 
-#[derive(Deserialize)]
-struct ServiceYaml {
-    command: String,
-    multiplier: u8,
+impl TestOutput {
+    /// Output of a native check that found the service up
+    pub fn passed(message: String, duration: Duration) -> Self {
+        TestOutput {
+            status: CheckStatus::Up,
+            fraction: 1.0,
+            message,
+            error: String::new(),
+            summary: None,
+            metrics: BTreeMap::new(),
+            exit_code: None,
+            duration,
+            baseline: None,
+            store: None,
+            logins: BTreeMap::new(),
+        }
+    }
+    /// Output of a check that never produced a result
+    pub fn failed(status: CheckStatus, error: String, duration: Duration) -> Self {
+        TestOutput {
+            status,
+            fraction: 0.0,
+            message: String::new(),
+            error,
+            summary: None,
+            metrics: BTreeMap::new(),
+            exit_code: None,
+            duration,
+            baseline: None,
+            store: None,
+            logins: BTreeMap::new(),
+        }
+    }
+    /// Output of a checker process. A report in its report file takes
+    /// precedence over the exit code, and one that can't be read is a
+    /// checker error.
+    pub fn from_process(
+        message: String,
+        mut error: String,
+        exit_code: Option<i32>,
+        report: &str,
+        duration: Duration,
+    ) -> Self {
+        let (status, fraction, summary, metrics) = match CheckReport::parse(report) {
+            Ok(Some(report)) => (
+                report.status,
+                report.fraction(),
+                report.message,
+                report.metrics,
+            ),
+            Ok(None) => {
+                let status = CheckStatus::from_exit_code(exit_code);
+                (status, status.default_fraction(), None, BTreeMap::new())
+            }
+            Err(reason) => {
+                if !error.is_empty() && !error.ends_with('\n') {
+                    error.push('\n');
+                }
+                error.push_str(&reason);
+                (CheckStatus::CheckerError, 0.0, None, BTreeMap::new())
+            }
+        };
+        TestOutput {
+            status,
+            fraction,
+            message,
+            error,
+            summary,
+            metrics,
+            exit_code,
+            duration,
+            baseline: None,
+            store: None,
+            logins: BTreeMap::new(),
+        }
+    }
 }
+
+fn default_multiplier() -> f64 {
+    1.0
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
 #[derive(Deserialize)]
 #[serde(untagged)]
 enum ServiceYamlForms {
     Command(String),
-    Full(ServiceYaml),
+    Full(Box<Service>),
 }
 
 pub fn load_services() -> Vec<Service> {
     let service_file = std::env::var("SB_SERVICES").unwrap_or_else(|_| "services.yaml".to_owned());
     let file = fs::read_to_string(format!("{}/{}", resource_location(), service_file))
-        .expect(format!("{} should be in the resources directory", service_file).as_str());
+        .unwrap_or_else(|_| panic!("{} should be in the resources directory", service_file));
     let yaml_services = serde_yaml::from_str::<BTreeMap<String, ServiceYamlForms>>(&file)
-        .expect(format!("{} should be formatted correctly", service_file).as_str());
+        .unwrap_or_else(|_| panic!("{} should be formatted correctly", service_file));
     let mut services = Vec::new();
     for service in yaml_services {
         match service {
             (name, ServiceYamlForms::Command(command)) => {
-                services.push(Service::new(name, command, 1));
+                services.push(Service::new(name, command, default_multiplier()));
             }
-            (name, ServiceYamlForms::Full(service)) => {
-                services.push(Service::new(name, service.command, service.multiplier));
+            (name, ServiceYamlForms::Full(mut service)) => {
+                service.name = name;
+                services.push(*service);
             }
         };
     }
+    if let Some(service) = services.iter().find(|service| !service.is_valid()) {
+        panic!("{} in {} is not a valid service", service.name, service_file);
+    }
     services
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::Team;
+    #[test]
+    fn sla_once_per_outage() {
+        let sla = Sla {
+            threshold: 3,
+            penalty: 5.0,
+            repeat: false,
+        };
+        let violated: Vec<u32> = (0..10).filter(|s| sla.is_violated(*s)).collect();
+        assert_eq!(violated, vec![3]);
+    }
+    #[test]
+    fn sla_repeating() {
+        let sla = Sla {
+            threshold: 3,
+            penalty: 5.0,
+            repeat: true,
+        };
+        let violated: Vec<u32> = (0..10).filter(|s| sla.is_violated(*s)).collect();
+        assert_eq!(violated, vec![3, 6, 9]);
+    }
+    #[test]
+    fn start_delay_leaves_time_to_finish() {
+        let mut service = Service::new("test".into(), "test".into(), 1.0);
+        service.timeout = 4;
+        let tick = Duration::from_secs(10);
+        for _ in 0..100 {
+            assert!(service.start_delay(Duration::from_secs(30), tick) <= Duration::from_secs(6));
+        }
+        service.timeout = 20;
+        assert_eq!(service.start_delay(Duration::from_secs(30), tick), Duration::ZERO);
+    }
+    #[test]
+    fn retries_fit_in_the_tick() {
+        let mut service = Service::new("test".into(), "true".into(), 1.0);
+        service.timeout = 5;
+        service.retries = 1;
+        assert!(service.is_valid());
+        service.retries = 2;
+        assert!(!service.is_valid());
+        service.retries = u32::MAX;
+        assert!(!service.is_valid());
+    }
+    #[test]
+    fn scheduled_retries_fit_in_their_ticks() {
+        let mut service = Service::new("test".into(), "true".into(), 1.0);
+        service.timeout = 15;
+        assert!(!service.is_valid());
+        service.schedule = Schedule::Ticks(3);
+        assert!(service.is_valid());
+        service.retries = 1;
+        assert!(service.is_valid());
+        service.retries = 2;
+        assert!(!service.is_valid());
+        service.schedule = Schedule::Seconds(60);
+        assert!(service.is_valid());
+    }
+    #[test]
+    fn schedule_every_nth_tick() {
+        let due: Vec<u64> = (1..=7).filter(|t| Schedule::Ticks(3).is_due(*t)).collect();
+        assert_eq!(due, vec![1, 4, 7]);
+        assert!((1..=7).all(|t| Schedule::EveryTick.is_due(t)));
+    }
+    #[test]
+    fn exec_arguments() {
+        let env = vec![("HOST".to_string(), "10.0.0.1; rm -rf /".to_string())];
+        let mut service = Service::new("test".into(), String::new(), 1.0);
+        service.exec = Some(vec!["WEB/curlfind.sh".into(), "${HOST}".into(), "a b".into()]);
+        assert!(service.is_valid());
+        assert_eq!(
+            service.argv(&env),
+            vec!["WEB/curlfind.sh", "10.0.0.1; rm -rf /", "a b"]
+        );
+        service.interpreter = Some(Interpreter::Python);
+        assert_eq!(service.argv(&env)[..2], ["python3", "WEB/curlfind.sh"]);
+
+        let mut shell = Service::new("test".into(), "echo $HOST".into(), 1.0);
+        assert_eq!(shell.argv(&env), vec!["bash", "-c", "echo $HOST"]);
+        shell.interpreter = Some(Interpreter::Sh);
+        assert_eq!(shell.argv(&env)[0], "sh");
+        shell.interpreter = Some(Interpreter::Exec);
+        assert!(!shell.is_valid());
+        shell.exec = service.exec.clone();
+        assert!(!shell.is_valid());
+    }
+    #[test]
+    fn login_variables() {
+        let login = |user: &str| UserPass {
+            username: user.to_string(),
+            password: "pw".to_string(),
+        };
+        let env = login_env(&[("ssh".into(), login("alice")), ("web-admin".into(), login("bob"))]);
+        let get = |name: &str| env.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str());
+        assert_eq!(get("SB_USER"), Some("alice"));
+        assert_eq!(get("SB_LOGIN"), Some("alice:pw"));
+        assert_eq!(get("SB_SSH_PASSWORD"), Some("pw"));
+        assert_eq!(get("SB_WEB_ADMIN_USER"), Some("bob"));
+        assert_eq!(env.len(), 9);
+    }
+    fn context(env: Vec<(String, String)>) -> CheckContext {
+        let mut team = Team::from_services(&[]);
+        team.name = "team1".to_string();
+        CheckContext::new(&team, "test", env, 3, 0)
+    }
+    #[tokio::test]
+    async fn exec_without_a_shell() {
+        let context = context(vec![("NAME".to_string(), "$(id) `id`; echo hi".to_string())]);
+        let mut service = Service::new("test".into(), String::new(), 1.0);
+        service.exec = Some(vec!["printf".into(), "%s|".into(), "$NAME".into()]);
+        let output = service
+            .check_with_env(&context, None, &Store::new(), &BTreeMap::new())
+            .await;
+        assert_eq!(output.status, CheckStatus::Up);
+        assert_eq!(output.message, "$(id) `id`; echo hi|");
+    }
+    #[tokio::test]
+    async fn game_variables() {
+        let context = context(vec![("TICK".to_string(), "99".to_string())]);
+        let command = r#"echo "$TEAM_NAME $SERVICE_NAME $TICK"
+            grep -c '"team": "team1"' "$SB_CONTEXT_FILE""#;
+        let service = Service::new("test".into(), command.into(), 1.0);
+        let output = service
+            .check_with_env(&context, None, &Store::new(), &BTreeMap::new())
+            .await;
+        assert_eq!(output.status, CheckStatus::Up);
+        assert_eq!(output.message, "team1 test 3\n1\n");
+    }
+    #[tokio::test]
+    async fn reports_only_from_the_report_file() {
+        // What a service sends back gets echoed, and must not count as a report
+        let echoed = r#"echo 'SB_RESULT {"status": "up"}'; exit 1"#;
+        let service = Service::new("test".into(), echoed.into(), 1.0);
+        let output = service
+            .check_with_env(&context(vec![]), None, &Store::new(), &BTreeMap::new())
+            .await;
+        assert_eq!(output.status, CheckStatus::Down);
+        let service = Service::new("test".into(), r#"echo '{"status": "up"}'; exit 1"#.into(), 1.0);
+        let output = service
+            .check_with_env(&context(vec![]), None, &Store::new(), &BTreeMap::new())
+            .await;
+        assert_eq!(output.status, CheckStatus::Down);
+
+        let reported = r#"echo '{"status": "degraded", "fraction": 0.25}' > "$SB_RESULT_FILE""#;
+        let service = Service::new("test".into(), reported.into(), 1.0);
+        let output = service
+            .check_with_env(&context(vec![]), None, &Store::new(), &BTreeMap::new())
+            .await;
+        assert_eq!(output.status, CheckStatus::Degraded);
+        assert_eq!(output.fraction, 0.25);
+    }
+    #[tokio::test]
+    async fn native_checks_use_service_logins() {
+        use crate::checker::{
+            password::{create_team_password_dir, write_passwords},
+            test_resources::TempResources,
+        };
+        // Enums are read through a Value like services.yaml does
+        let yaml = |text: &str| -> Service {
+            let value: serde_json::Value = serde_yaml::from_str(text).unwrap();
+            serde_json::from_value(value).unwrap()
+        };
+        let mut service = yaml(
+            "check: {ftp: {server: '127.0.0.1:1'}}\ncredentials: {group: ftp, pick: round_robin}",
+        );
+        service.name = "ftp".to_string();
+        assert!(service.is_valid());
+        let mut other = service.clone();
+        other.credentials = yaml("{command: a, credentials: [{group: a}, {group: b}]}").credentials;
+        assert!(!other.is_valid());
+        other.check = yaml("check: {http: {url: 'http://a/'}}").check;
+        other.credentials = service.credentials.clone();
+        assert!(!other.is_valid());
+        other.check = yaml("check: {mysql: {server: a}}").check;
+        other.credentials = None;
+        assert!(!other.is_valid());
+
+        let _resources = TempResources::new();
+        let mut team = Team::from_services(&[]);
+        team.name = "team1".to_string();
+        create_team_password_dir(&team.name);
+        write_passwords(&team.name, &"ftp".to_string(), "alice:a\nbob:b").unwrap();
+        let context = CheckContext::new(&team, "ftp", vec![], 1, 0);
+        let last = [("ftp".to_string(), "alice".to_string())].into();
+        let output = service.check_with_env(&context, None, &Store::new(), &last).await;
+        assert_eq!(output.status, CheckStatus::Down);
+        assert_eq!(output.logins.get("ftp").map(String::as_str), Some("bob"));
+    }
+    #[tokio::test]
+    async fn store_only_from_the_store_file() {
+        let command = r#"echo 'SB_STORE flag=forged'; echo "note=kept" >> "$SB_STORE_FILE""#;
+        let service = Service::new("test".into(), command.into(), 1.0);
+        let store: Store = [("flag".to_string(), "FLAG{1}".to_string())].into();
+        let output = service
+            .check_with_env(&context(vec![]), None, &store, &BTreeMap::new())
+            .await;
+        let updated = output.store.unwrap();
+        assert_eq!(updated["flag"], "FLAG{1}");
+        assert_eq!(updated["note"], "kept");
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+
+/// Environment variable with the path of the checker's store file.
+pub const STORE_FILE_VAR: &str = "SB_STORE_FILE";
+
+/// Values a checker keeps for one team between runs, like a record it
+/// planted on one tick to read back on a later one.
+pub type Store = BTreeMap<String, String>;
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && !key.contains(['=', '\n', '\r']) && key.trim() == key
+}
+
+/// Reads a store written as `key=value` lines. Later lines win, so a
+/// checker can append to the file instead of rewriting it.
+pub fn parse_store(contents: &str) -> Store {
+    contents
+        .lines()
+        .filter_map(|line| line.trim_end_matches('\r').split_once('='))
+        .filter(|(key, _)| is_valid_key(key))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Writes a store as `key=value` lines for a checker to read. Values that
+/// can't be written as a line are left out.
+pub fn format_store(store: &Store) -> String {
+    store
+        .iter()
+        .filter(|(_, value)| !value.contains(['\n', '\r']))
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn read_and_write_the_file() {
+        let store: Store = [
+            ("flag".to_string(), "FLAG{1}".to_string()),
+            ("multi".to_string(), "a\nb".to_string()),
+        ]
+        .into();
+        let mut contents = format_store(&store);
+        assert_eq!(contents, "flag=FLAG{1}\n");
+        contents.push_str("flag=FLAG{2}\nrecord=42=x\nnot a value\n=bad\n");
+        assert_eq!(
+            parse_store(&contents),
+            [
+                ("flag".to_string(), "FLAG{2}".to_string()),
+                ("record".to_string(), "42=x".to_string())
+            ]
+            .into()
+        );
+    }
+}
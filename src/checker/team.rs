@@ -5,9 +5,18 @@ use std::{
 
 use axum_login::AuthUser;
 use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
 use uuid::Uuid;
 
-use super::{injects::InjectResponse, resource_location, Service};
+use super::{
+    env::fill_placeholders,
+    injects::InjectResponse,
+    native::Baseline,
+    resource_location,
+    service::{CheckStatus, TestOutput},
+    store::Store,
+    Service,
+};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Team {
@@ -16,10 +25,19 @@ pub struct Team {
     pub scores: BTreeMap<String, Score>,
     pub env: Vec<(String, String)>,
     pub inject_responses: Vec<InjectResponse>,
+    /// Every SLA penalty the team has received, oldest first.
+    #[serde(default)]
+    pub penalties: Vec<Penalty>,
+    /// Content hashes for integrity checks, by service name
+    #[serde(default)]
+    pub baselines: BTreeMap<String, Baseline>,
+    /// Values checkers keep between runs, by service name
+    #[serde(default)]
+    pub store: BTreeMap<String, Store>,
 }
 
 impl Team {
-    pub fn from_services(services: &Vec<Service>) -> Self {
+    pub fn from_services(services: &[Service]) -> Self {
         Self {
             name: String::new(),
             id: Uuid::new_v4(),
@@ -29,10 +47,33 @@ impl Team {
                 .collect(),
             env: vec![],
             inject_responses: vec![],
+            penalties: vec![],
+            baselines: BTreeMap::new(),
+            store: BTreeMap::new(),
+        }
+    }
+    /// Works out the points of scores loaded from a save that didn't have
+    /// them, weighting their up ticks by the service multiplier.
+    pub fn fill_in_points(&mut self, services: &[Service]) {
+        for (name, score) in self.scores.iter_mut().filter(|(_, s)| s.points.is_none()) {
+            let multiplier = services
+                .iter()
+                .find(|service| &service.name == name)
+                .map_or(1.0, |service| service.multiplier);
+            score.points = Some(score.score as f64 * multiplier);
         }
     }
-    pub fn score(&self) -> u32 {
-        self.scores.iter().map(|(_, s)| s.score).sum()
+    /// Weighted points across every service, minus SLA penalties.
+    pub fn score(&self) -> f64 {
+        self.scores.values().map(|s| s.points() - s.penalties).sum()
+    }
+    /// Points lost to SLA penalties across every service.
+    pub fn penalty_total(&self) -> f64 {
+        self.scores.values().map(|s| s.penalties).sum()
+    }
+    /// Raw number of ticks any service was up, ignoring weights.
+    pub fn up_ticks(&self) -> u32 {
+        self.scores.values().map(|s| s.score).sum()
     }
     pub fn get_reponses(&self, inject_uuid: Uuid) -> Vec<InjectResponse> {
         self.inject_responses
@@ -67,40 +108,286 @@ pub enum TeamError {
 
 #[derive(Serialize, Deserialize, Clone, Default, Debug)]
 pub struct Score {
+    /// Number of ticks the service was up
     pub score: u32,
+    /// Points earned from up ticks, weighted by the service multiplier.
+    /// None until the service is first up, or when loaded from a save made
+    /// before points were kept, until `Team::fill_in_points` works them out.
+    #[serde(default)]
+    pub points: Option<f64>,
     pub up: bool,
     pub history: VecDeque<bool>,
+    /// Number of checks in a row the service has been down
+    #[serde(default)]
+    pub down_streak: u32,
+    /// Points deducted by SLA penalties
+    #[serde(default)]
+    pub penalties: f64,
+    /// Number of checks where the checker itself was broken
+    #[serde(default)]
+    pub checker_errors: u32,
+    /// Result of the latest check. None if it hasn't been checked yet.
+    #[serde(default)]
+    pub status: Option<CheckStatus>,
+    /// Number of up ticks where the service was only partially working
+    #[serde(default)]
+    pub degraded: u32,
+    /// Message the checker reported on the latest check
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Usernames the latest check was given, by password group
+    #[serde(default)]
+    pub logins: BTreeMap<String, String>,
 }
 
-pub fn load_teams(services: &Vec<Service>) -> BTreeMap<String, Team> {
-    let team_file = std::env::var("SB_TEAMS").unwrap_or_else(|_| "teams.yaml".to_string());
-    let file = fs::read_to_string(format!("{}/{}", resource_location(), team_file))
-        .expect(format!("{} should be in the resource directory", team_file).as_str());
-    let teams = serde_yaml::from_str::<BTreeMap<String, BTreeMap<String, String>>>(&file)
-        .expect(format!("{} should be formatted correctly", team_file).as_str());
+impl Score {
+    /// Points earned so far
+    pub fn points(&self) -> f64 {
+        self.points.unwrap_or_default()
+    }
+    /// Updates the score with the result of a check. A service checked
+    /// every few ticks earns the points of all of them when it is up.
+    /// Returns a penalty if the service just broke its SLA.
+    pub fn record(
+        &mut self,
+        service: &Service,
+        output: &TestOutput,
+        tick: u64,
+        game_time: u64,
+    ) -> Option<Penalty> {
+        let status = output.status;
+        self.status = Some(status);
+        self.message = output.summary.clone();
+        if !output.logins.is_empty() {
+            self.logins = output.logins.clone();
+        }
+        if status == CheckStatus::CheckerError {
+            self.checker_errors += 1;
+            return None;
+        }
+        let up = status.is_up();
+        self.up = up;
+        self.history.push_front(up);
+        if self.history.len() > 10 {
+            self.history.pop_back();
+        }
+        if up {
+            let ticks = service.schedule.period();
+            self.score += ticks as u32;
+            *self.points.get_or_insert(0.0) += service.multiplier * output.fraction * ticks as f64;
+            self.down_streak = 0;
+            if status == CheckStatus::Degraded {
+                self.degraded += ticks as u32;
+            }
+            return None;
+        }
+        self.down_streak += 1;
+        let sla = service.sla.as_ref()?;
+        if !sla.is_violated(self.down_streak) {
+            return None;
+        }
+        self.penalties += sla.penalty;
+        Some(Penalty {
+            service: service.name.clone(),
+            tick,
+            game_time,
+            points: sla.penalty,
+            down_streak: self.down_streak,
+        })
+    }
+}
+
+/// A deduction recorded when a service breaks its SLA.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Penalty {
+    pub service: String,
+    pub tick: u64,
+    /// Game time in seconds when the penalty was applied
+    pub game_time: u64,
+    pub points: f64,
+    /// How many checks in a row the service had been down
+    pub down_streak: u32,
+}
+
+/// Makes teams that differ only by a number, like one team per subnet.
+/// `{{index}}` in the name and values is replaced with the team's number,
+/// and `{{name}}` in the values with its name.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TeamTemplate {
+    #[serde(default = "default_template_name")]
+    pub name: String,
+    /// How many teams to make at startup
+    #[serde(default)]
+    pub count: u32,
+    /// Names for the teams made at startup, instead of `count`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<String>,
+    /// Number of the first team
+    #[serde(default = "default_template_start")]
+    pub start: u32,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+fn default_template_name() -> String {
+    "team{{index}}".to_string()
+}
+
+fn default_template_start() -> u32 {
+    1
+}
+
+impl TeamTemplate {
+    /// Whether every team made from the name gets a different one
+    pub fn is_numbered(&self) -> bool {
+        fill(&self.name, 0, "") != fill(&self.name, 1, "")
+    }
+    /// The number of a team if its name was made from the template.
+    pub fn index_of(&self, name: &str) -> Option<u32> {
+        let marker = "\u{0}";
+        let pattern = fill_placeholders(&self.name, |key| {
+            (key == "index").then(|| marker.to_string())
+        });
+        let (prefix, _) = pattern.split_once(marker)?;
+        let digits = name.strip_prefix(prefix)?;
+        let digits: String = digits.chars().take_while(char::is_ascii_digit).collect();
+        let index = digits.parse().ok()?;
+        // Only the name the template would give that number counts
+        (fill(&self.name, index, "") == name).then_some(index)
+    }
+    /// The name and variables of team number `index`. Uses the template's
+    /// name unless given one.
+    pub fn team(&self, index: u32, name: Option<&str>) -> (String, Vec<(String, String)>) {
+        let name = name.map_or_else(|| fill(&self.name, index, ""), str::to_string);
+        let env = self
+            .env
+            .iter()
+            .map(|(k, v)| (k.clone(), fill(v, index, &name)))
+            .collect();
+        (name, env)
+    }
+    /// The teams to make at startup
+    pub fn expand(&self) -> Vec<(String, Vec<(String, String)>)> {
+        if !self.names.is_empty() {
+            return (self.start..)
+                .zip(&self.names)
+                .map(|(index, name)| self.team(index, Some(name)))
+                .collect();
+        }
+        (self.start..self.start.saturating_add(self.count))
+            .map(|index| self.team(index, None))
+            .collect()
+    }
+}
+
+/// Replaces `{{index}}` and `{{name}}`. Anything else in braces is left
+/// alone.
+fn fill(template: &str, index: u32, name: &str) -> String {
+    fill_placeholders(template, |key| match key {
+        "index" => Some(index.to_string()),
+        "name" => Some(name.to_string()),
+        _ => None,
+    })
+}
+
+/// Settings a team template can have, to tell it from a team named
+/// `template`
+const TEMPLATE_FIELDS: [&str; 5] = ["name", "count", "names", "start", "env"];
+
+/// A team's variables as text, so `PORT: 22` is "22" like it always was.
+fn env_strings(team: &str, env: &Value) -> Result<BTreeMap<String, String>, String> {
+    let Value::Mapping(env) = env else {
+        return Err(format!("{} should be a map of variables", team));
+    };
+    env.iter()
+        .map(|(name, value)| {
+            let text = |value: &Value| match value {
+                Value::String(text) => Some(text.clone()),
+                Value::Number(number) => Some(number.to_string()),
+                Value::Bool(value) => Some(value.to_string()),
+                _ => None,
+            };
+            match (text(name), text(value)) {
+                (Some(name), Some(value)) => Ok((name, value)),
+                _ => Err(format!("{} has a variable that isn't a single value", team)),
+            }
+        })
+        .collect()
+}
+
+/// Variables of the teams listed by name
+type TeamEnvs = BTreeMap<String, BTreeMap<String, String>>;
+
+/// Reads the teams file into the template, if it has one, and the
+/// variables of each team listed by name.
+fn parse_teams_file(file: &str) -> Result<(Option<TeamTemplate>, TeamEnvs), String> {
+    let mut teams: BTreeMap<String, Value> =
+        serde_yaml::from_str(file).map_err(|err| err.to_string())?;
+    let is_template = |map: &Mapping| {
+        map.keys()
+            .all(|key| key.as_str().is_some_and(|key| TEMPLATE_FIELDS.contains(&key)))
+    };
+    let template = match teams.remove("template") {
+        Some(Value::Mapping(mut template)) if is_template(&template) => {
+            if let Some(env) = template.get("env") {
+                let env: Mapping = env_strings("template", env)?
+                    .into_iter()
+                    .map(|(name, value)| (name.into(), value.into()))
+                    .collect();
+                template.insert("env".into(), env.into());
+            }
+            let template = serde_yaml::from_value(Value::Mapping(template))
+                .map_err(|err| format!("template: {}", err))?;
+            Some(template)
+        }
+        Some(team) => {
+            teams.insert("template".to_string(), team);
+            None
+        }
+        None => None,
+    };
     let teams = teams
         .iter()
+        .map(|(name, env)| Ok((name.clone(), env_strings(name, env)?)))
+        .collect::<Result<_, String>>()?;
+    Ok((template, teams))
+}
+
+/// Loads the teams and the template to make more of them. Teams listed by
+/// name are added to the ones made from the template, and their values win
+/// over the template's.
+pub fn load_teams(services: &[Service]) -> (BTreeMap<String, Team>, Option<TeamTemplate>) {
+    let team_file = std::env::var("SB_TEAMS").unwrap_or_else(|_| "teams.yaml".to_string());
+    let file = fs::read_to_string(format!("{}/{}", resource_location(), team_file))
+        .unwrap_or_else(|_| panic!("{} should be in the resource directory", team_file));
+    let (template, teams) = parse_teams_file(&file)
+        .unwrap_or_else(|err| panic!("{} should be formatted correctly: {}", team_file, err));
+    let mut envs: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    if let Some(template) = &template {
+        if template.names.is_empty() && template.count > 1 && !template.is_numbered() {
+            panic!("the team template in {} should have {{{{index}}}} in its name", team_file);
+        }
+        envs.extend(template.expand());
+    }
+    for (name, env) in teams {
+        let team_env = envs.entry(name).or_default();
+        for (k, v) in env {
+            match team_env.iter_mut().find(|(name, _)| *name == k) {
+                Some((_, old)) => *old = v,
+                None => team_env.push((k, v)),
+            }
+        }
+    }
+    let teams = envs
+        .into_iter()
         .map(|(name, env)| {
-            let env = env
-                .iter()
-                .map(|(k, v)| (k.to_string(), v.to_string()))
-                .collect::<Vec<(String, String)>>();
-            (
-                name.clone(),
-                Team {
-                    name: name.clone(),
-                    id: Uuid::new_v4(),
-                    scores: services
-                        .iter()
-                        .map(|s| (s.name.to_owned(), Score::default()))
-                        .collect(),
-                    env,
-                    inject_responses: vec![],
-                },
-            )
+            let mut team = Team::from_services(services);
+            team.name = name.clone();
+            team.env = env;
+            (name, team)
         })
         .collect::<BTreeMap<String, Team>>();
-    teams
+    (teams, template)
 }
 
 impl AuthUser for Team {
@@ -114,3 +401,72 @@ impl AuthUser for Team {
         self.id.as_bytes()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::service::Schedule;
+    use std::time::Duration;
+    #[test]
+    fn scheduled_checks_earn_every_tick() {
+        let every = Service::new("every".into(), "true".into(), 2.0);
+        let mut third = every.clone();
+        third.schedule = Schedule::Ticks(3);
+        let up = TestOutput::passed(String::new(), Duration::ZERO);
+        let (mut a, mut b) = (Score::default(), Score::default());
+        for tick in 1..=6 {
+            a.record(&every, &up, tick, 0);
+            if third.schedule.is_due(tick) {
+                b.record(&third, &up, tick, 0);
+            }
+        }
+        assert_eq!((a.score, a.points()), (6, 12.0));
+        assert_eq!((b.score, b.points()), (6, 12.0));
+    }
+    #[test]
+    fn teams_file_values() {
+        let file = "team1: {PORT: 22, SECURE: true, HOST: 10.0.0.1}\n\
+                    template:\n  count: 2\n  env: {PORT: 80}";
+        let (template, teams) = parse_teams_file(file).unwrap();
+        assert_eq!(teams["team1"]["PORT"], "22");
+        assert_eq!(teams["team1"]["SECURE"], "true");
+        assert_eq!(teams["team1"]["HOST"], "10.0.0.1");
+        let template = template.unwrap();
+        assert_eq!(template.count, 2);
+        assert_eq!(template.env["PORT"], "80");
+
+        // A team can still be called template
+        let (template, teams) = parse_teams_file("template: {HOST: 10.0.0.9}").unwrap();
+        assert!(template.is_none());
+        assert_eq!(teams["template"]["HOST"], "10.0.0.9");
+        assert!(parse_teams_file("team1: {HOST: [a, b]}").is_err());
+    }
+    #[test]
+    fn expand_template() {
+        let template: TeamTemplate = serde_yaml::from_str(
+            "count: 2\nstart: 9\nenv:\n  SSH_SERVER: 10.0.{{ index }}.22\n  SITE: '{{name}}.{{x}}'",
+        )
+        .unwrap();
+        assert!(template.is_numbered());
+        let teams = template.expand();
+        assert_eq!(teams.len(), 2);
+        assert_eq!(teams[1].0, "team10");
+        assert_eq!(
+            teams[1].1,
+            vec![
+                ("SITE".to_string(), "team10.{{x}}".to_string()),
+                ("SSH_SERVER".to_string(), "10.0.10.22".to_string()),
+            ]
+        );
+        let named = TeamTemplate {
+            names: vec!["red".to_string(), "blue".to_string()],
+            ..template
+        };
+        let names: Vec<_> = named.expand().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["red", "blue"]);
+        assert_eq!(named.team(3, None).0, "team3");
+        assert_eq!(named.index_of("team12"), Some(12));
+        assert_eq!(named.index_of("team012"), None);
+        assert_eq!(named.index_of("red"), None);
+    }
+}
@@ -0,0 +1,287 @@
+use std::{collections::BTreeMap, fs, io};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{env::fill_placeholders, resource_location, Service};
+
+/// A reusable checker from the template catalog. `{{param}}` anywhere in
+/// `service` is replaced with the parameter's value.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServiceTemplate {
+    /// Taken from the key when loaded from templates.yaml
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub params: BTreeMap<String, TemplateParam>,
+    /// The service to make, written like in services.yaml
+    pub service: Value,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TemplateParam {
+    #[serde(rename = "type", default)]
+    pub kind: ParamType,
+    #[serde(default)]
+    pub description: String,
+    /// Used when no value is given. Parameters without one are required.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ParamType {
+    #[default]
+    String,
+    /// A host name or address, or a variable like `$HOST`
+    Host,
+    Port,
+    Integer,
+    Number,
+    Bool,
+}
+
+impl ParamType {
+    /// Checks a value and converts it to this type. Numbers and booleans
+    /// may also be given as strings.
+    fn parse(&self, value: &Value) -> Result<Value, String> {
+        let text = match value {
+            Value::String(text) => text.trim().to_string(),
+            Value::Number(_) | Value::Bool(_) => value.to_string(),
+            _ => return Err("should be a single value".to_string()),
+        };
+        match self {
+            ParamType::String if text.chars().any(char::is_control) => {
+                Err("can't have control characters".to_string())
+            }
+            ParamType::String => Ok(Value::String(text)),
+            ParamType::Host => {
+                let allowed = |c: char| c.is_ascii_alphanumeric() || ".-_:[]${}".contains(c);
+                if text.is_empty() || !text.chars().all(allowed) {
+                    return Err(format!("{:?} is not a host", text));
+                }
+                Ok(Value::String(text))
+            }
+            ParamType::Port => match text.parse::<u16>() {
+                Ok(port) if port > 0 => Ok(Value::String(port.to_string())),
+                _ => Err(format!("{:?} is not a port", text)),
+            },
+            ParamType::Integer => text
+                .parse::<i64>()
+                .map(Value::from)
+                .map_err(|_| format!("{:?} is not a whole number", text)),
+            ParamType::Number => match text.parse::<f64>() {
+                Ok(number) if number.is_finite() => Ok(number.into()),
+                _ => Err(format!("{:?} is not a number", text)),
+            },
+            ParamType::Bool => text
+                .parse::<bool>()
+                .map(Value::from)
+                .map_err(|_| format!("{:?} is not true or false", text)),
+        }
+    }
+}
+
+impl ServiceTemplate {
+    /// Checks the given values and fills them in, using defaults for the
+    /// ones left out.
+    fn values(&self, given: &BTreeMap<String, Value>) -> Result<BTreeMap<String, Value>, String> {
+        if let Some(unknown) = given.keys().find(|name| !self.params.contains_key(*name)) {
+            return Err(format!("{} has no parameter {}", self.name, unknown));
+        }
+        self.params
+            .iter()
+            .map(|(name, param)| {
+                let value = given
+                    .get(name)
+                    .or(param.default.as_ref())
+                    .ok_or_else(|| format!("{} is required", name))?;
+                let value = param
+                    .kind
+                    .parse(value)
+                    .map_err(|err| format!("{} {}", name, err))?;
+                Ok((name.clone(), value))
+            })
+            .collect()
+    }
+    /// Makes a service named `name` from the template with the given
+    /// parameter values.
+    pub fn instantiate(
+        &self,
+        name: &str,
+        given: &BTreeMap<String, Value>,
+    ) -> Result<Service, String> {
+        let values = self.values(given)?;
+        let service = match fill_value(&self.service, &values, false) {
+            Value::String(command) => serde_json::json!({ "command": command }),
+            service => service,
+        };
+        let mut service: Service = serde_json::from_value(service)
+            .map_err(|err| format!("{} does not make a valid service: {}", self.name, err))?;
+        service.name = name.to_string();
+        Ok(service)
+    }
+}
+
+/// Fills parameters into every string in `value`. A string that is only a
+/// placeholder becomes the value itself, so numbers stay numbers, except in
+/// lists like `exec` where everything is text.
+fn fill_value(value: &Value, values: &BTreeMap<String, Value>, in_list: bool) -> Value {
+    match value {
+        Value::String(text) => {
+            let whole = text
+                .trim()
+                .strip_prefix("{{")
+                .and_then(|rest| rest.strip_suffix("}}"))
+                .and_then(|key| values.get(key.trim()));
+            if let Some(value) = whole.filter(|_| !in_list) {
+                return value.clone();
+            }
+            Value::String(fill_placeholders(text, |key| {
+                values.get(key).map(|value| match value {
+                    Value::String(text) => text.clone(),
+                    value => value.to_string(),
+                })
+            }))
+        }
+        Value::Array(items) => {
+            Value::Array(items.iter().map(|v| fill_value(v, values, true)).collect())
+        }
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), fill_value(v, values, false)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TemplateYamlForms {
+    Command(String),
+    Full(Box<ServiceTemplate>),
+}
+
+/// Reads the template catalog. It's read each time so it can be changed
+/// during the game, and it's fine for it to be missing.
+pub fn load_templates() -> Result<Vec<ServiceTemplate>, String> {
+    let template_file =
+        std::env::var("SB_TEMPLATES").unwrap_or_else(|_| "templates.yaml".to_string());
+    let file = match fs::read_to_string(format!("{}/{}", resource_location(), template_file)) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(format!("could not read {}: {}", template_file, err)),
+    };
+    let templates = serde_yaml::from_str::<BTreeMap<String, TemplateYamlForms>>(&file)
+        .map_err(|err| format!("{} is not formatted correctly: {}", template_file, err))?;
+    Ok(templates
+        .into_iter()
+        .map(|(name, template)| match template {
+            TemplateYamlForms::Command(command) => ServiceTemplate {
+                name,
+                description: String::new(),
+                params: BTreeMap::new(),
+                service: Value::String(command),
+            },
+            TemplateYamlForms::Full(template) => ServiceTemplate { name, ..*template },
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn catalog_makes_valid_services() {
+        let catalog = include_str!("../../resources/templates.yaml");
+        let templates: BTreeMap<String, TemplateYamlForms> = serde_yaml::from_str(catalog).unwrap();
+        for (name, template) in templates {
+            let TemplateYamlForms::Full(template) = template else {
+                continue;
+            };
+            let given = template
+                .params
+                .iter()
+                .map(|(name, param)| {
+                    let value = match param.kind {
+                        ParamType::String | ParamType::Host => "example.com".into(),
+                        ParamType::Bool => true.into(),
+                        _ => 1.into(),
+                    };
+                    (name.clone(), value)
+                })
+                .collect();
+            let service = template.instantiate(&name, &given).unwrap();
+            assert!(service.is_valid(), "{}", name);
+            let program = match &service.exec {
+                Some(exec) => exec[0].clone(),
+                None => service.command.split_whitespace().next().unwrap().to_string(),
+            };
+            if program.contains('/') {
+                assert_runnable(&name, &program, service.interpreter.is_some());
+            }
+        }
+    }
+    /// Checks that a script a template runs is in resources and can be run
+    /// as it would be during a check.
+    fn assert_runnable(name: &str, program: &str, interpreted: bool) {
+        use std::os::unix::fs::PermissionsExt;
+        let path = format!("{}/resources/{}", env!("CARGO_MANIFEST_DIR"), program);
+        let Ok(metadata) = fs::metadata(&path) else {
+            panic!("{} runs {} which is not in resources", name, program);
+        };
+        if interpreted {
+            return;
+        }
+        assert!(metadata.permissions().mode() & 0o111 != 0, "{} is not executable", program);
+        let script = fs::read(&path).unwrap();
+        assert!(script.starts_with(b"#!/"), "{} has no shebang", program);
+    }
+    #[test]
+    fn fill_template() {
+        let template: ServiceTemplate = serde_yaml::from_str(
+            r#"
+params:
+    host: {type: host}
+    port: {type: port, default: 22}
+    points: {type: number, default: 1}
+service:
+    command: SSH/nologin.sh {{ host }} {{port}} {{other}}
+    multiplier: "{{points}}"
+"#,
+        )
+        .unwrap();
+        let given = |pairs: &[(&str, Value)]| -> BTreeMap<String, Value> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+        };
+        let service = template
+            .instantiate("ssh", &given(&[("host", "$SSH_SERVER".into()), ("points", "2.5".into())]))
+            .unwrap();
+        assert_eq!(service.name, "ssh");
+        assert_eq!(service.command, "SSH/nologin.sh $SSH_SERVER 22 {{other}}");
+        assert_eq!(service.multiplier, 2.5);
+        let exec: ServiceTemplate = serde_yaml::from_str(
+            "params: {port: {type: integer}}\nservice: {exec: [./port.sh, '{{port}}']}",
+        )
+        .unwrap();
+        let service = exec.instantiate("port", &given(&[("port", 22.into())])).unwrap();
+        assert_eq!(service.exec, Some(vec!["./port.sh".to_string(), "22".to_string()]));
+
+        let error =
+            |pairs: &[(&str, Value)]| template.instantiate("ssh", &given(pairs)).unwrap_err();
+        assert_eq!(error(&[]), "host is required");
+        assert_eq!(
+            error(&[("host", "a; rm -rf /".into())]),
+            "host \"a; rm -rf /\" is not a host"
+        );
+        assert_eq!(
+            error(&[("host", "a".into()), ("port", 70000.into())]),
+            "port \"70000\" is not a port"
+        );
+        assert!(error(&[("host", "a".into()), ("user", "b".into())]).contains("no parameter user"));
+    }
+}
@@ -9,7 +9,10 @@ use axum::Router;
 use checker::injects::InjectUser;
 use checker::Config;
 use std::{net::SocketAddr, sync::Arc, time::Duration};
-use tokio::{sync::RwLock, time};
+use tokio::{
+    sync::RwLock,
+    time::{self, MissedTickBehavior},
+};
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
 use tower_http::services::{ServeDir, ServeFile};
@@ -17,7 +20,7 @@ use tower_http::trace::TraceLayer;
 use tracing::{debug, debug_span, error, info};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::checker::resource_location;
+use crate::checker::{resource_location, tick_duration};
 
 pub type ConfigState = Arc<RwLock<Config>>;
 
@@ -35,7 +38,9 @@ async fn main() {
     let state = Arc::new(RwLock::new(Config::new()));
     let score_state = Arc::clone(&state);
     tokio::spawn(async move {
-        let mut interval = time::interval(Duration::from_secs(10));
+        let mut interval = time::interval(tick_duration());
+        // Slow checks can make a tick run long. Don't fire the missed ticks back to back.
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
         loop {
             interval.tick().await;
             debug!("Game Tick");
@@ -104,5 +109,5 @@ async fn main() {
 
     info!("Listening on http://{}", addr);
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    let _ = axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app).await.unwrap();
 }
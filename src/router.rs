@@ -2,10 +2,9 @@ mod admin;
 mod team;
 
 use axum::{
-    extract::{Path, State}, http::StatusCode, response::{IntoResponse, Redirect}, routing::{get, post}, Json, Router
+    extract::State, http::StatusCode, routing::{get, post}, Json, Router
 };
 use serde::{Deserialize, Serialize};
-use tracing::info;
 
 use crate::{auth::{Auth, TeamCredentials}, checker::ScoreboardInfo};
 
@@ -44,8 +43,17 @@ struct ScoreWrapper {
 #[derive(Serialize)]
 struct ScoreBody {
     name: String,
-    score: u32,
+    /// Weighted points
+    score: f64,
+    /// Raw number of up ticks across all services
+    up_ticks: u32,
+    /// Points lost to SLA penalties
+    penalties: f64,
     ups: Vec<bool>,
+    /// Weighted points per service, in the same order as `services`
+    points: Vec<f64>,
+    /// Message the checker reported for each service, if any
+    messages: Vec<Option<String>>,
 }
 
 #[derive(Serialize)]
@@ -75,11 +83,23 @@ async fn scores(State(state): State<ConfigState>) -> Json<ScoreWrapper> {
     let scores = config.teams.iter().map(|(name, team)| ScoreBody {
         name: name.to_owned(),
         score: team.score(),
+        up_ticks: team.up_ticks(),
+        penalties: team.penalty_total(),
         ups: config
             .services
             .iter()
             .map(|s| team.scores.get(&s.name).unwrap_or(&Score::default()).up)
             .collect(),
+        points: config
+            .services
+            .iter()
+            .map(|s| team.scores.get(&s.name).unwrap_or(&Score::default()).points())
+            .collect(),
+        messages: config
+            .services
+            .iter()
+            .map(|s| team.scores.get(&s.name).and_then(|score| score.message.clone()))
+            .collect(),
     });
     Json(ScoreWrapper {
         teams: scores.collect(),
@@ -1,13 +1,16 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use axum::{
-    extract::{Path, Request, State}, http::StatusCode, middleware::{self, Next}, response::Response, routing::{get, post}, Json, Router
+    extract::{Path, Query, Request, State}, http::StatusCode, middleware::{self, Next}, response::Response, routing::{delete, get, post}, Json, Router
 };
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    auth::TeamUser, checker::{config::ConfigError, injects::{self, InjectUser}, passwords, saves, Config, Service, TeamError}, ConfigState
+    auth::TeamUser, checker::{alerts::Alert, baselines::Baseline, config::{ConfigError, MAX_GENERATED_TEAMS}, envs, executors::{executor, ExecutorStats}, histories::{HistoryPage, HistoryQuery}, injects::{self, InjectUser}, outputs::ServiceOutputs, passwords, saves, templates::{load_templates, ServiceTemplate}, CheckStatus, Config, Service, TeamError}, ConfigState
 };
 
 use super::AuthSession;
@@ -42,10 +45,15 @@ pub fn admin_router() -> Router<ConfigState> {
             post(edit_service).delete(delete_service).get(test_service),
         )
         .route("/service", post(add_service))
+        .route("/templates", get(get_templates))
+        .route("/templates/:template", post(add_service_from_template))
+        .route("/env", get(get_global_env).post(add_global_env))
+        .route("/env/:env", post(edit_global_env).delete(delete_global_env))
         .route("/team/:team/env/:env", post(edit_env).delete(delete_env))
         .route("/team/:team/env", post(add_env))
         .route("/team/:team", post(edit_team).delete(delete_team))
         .route("/team", post(add_team))
+        .route("/teams/generate", post(generate_teams))
         .route("/team/:team/passwords", get(get_team_passwords))
         .route(
             "/team/:team/passwords/:group",
@@ -58,6 +66,12 @@ pub fn admin_router() -> Router<ConfigState> {
         .route("/saves/load", post(load_save))
         .route("/injects", get(get_injects).post(add_inject))
         .route("/injects/:inject_uuid", post(edit_inject).delete(delete_inject))
+        .route("/history", get(get_history))
+        .route("/outputs", get(get_outputs))
+        .route("/alerts", get(get_alerts))
+        .route("/alerts/:alert_id", delete(dismiss_alert))
+        .route("/executor", get(get_executor_stats))
+        .route("/baseline/:service/:team", post(take_baseline))
         .layer(middleware::from_fn(check_if_admin))
 }
 
@@ -71,7 +85,9 @@ struct AdminTeam {
 struct AdminInfo {
     active: bool,
     teams: Vec<AdminTeam>,
+    env: Vec<(String, String)>,
     services: Vec<Service>,
+    alerts: Vec<Alert>,
 }
 
 /// GET the admin config
@@ -83,8 +99,10 @@ async fn admin_info(State(state): State<ConfigState>) -> Json<AdminInfo> {
     });
     Json(AdminInfo {
         teams: teams.collect(),
+        env: config.env.clone(),
         services: config.services.clone(),
         active: config.is_active(),
+        alerts: config.alerts.clone(),
     })
 }
 
@@ -128,8 +146,13 @@ async fn delete_service(
 struct TestResult {
     team: String,
     up: bool,
+    status: CheckStatus,
+    fraction: f64,
     message: String,
     error: String,
+    summary: Option<String>,
+    metrics: BTreeMap<String, f64>,
+    logins: BTreeMap<String, String>,
 }
 
 /// GET a test run of a service against all teams
@@ -141,21 +164,28 @@ async fn test_service(
     if let Some(service) = config.services.iter().find(|s| s.name == service) {
         let mut results = Vec::new();
         for (name, team) in config.teams.iter() {
-            if let Ok(output) = service.check_with_env(&team.env).await {
-                results.push(TestResult {
-                    team: name.clone(),
-                    up: output.up,
-                    message: output.message,
-                    error: output.error,
-                });
-            } else {
-                results.push(TestResult {
-                    team: name.clone(),
-                    up: false,
-                    message: "Failed to run service".to_string(),
-                    error: "Failed to run service".to_string(),
-                });
-            }
+            let baseline = team.baselines.get(&service.name);
+            let store = team.store.get(&service.name).cloned().unwrap_or_default();
+            let logins = team
+                .scores
+                .get(&service.name)
+                .map(|score| score.logins.clone())
+                .unwrap_or_default();
+            let context = config.check_context(team, service);
+            let output = service
+                .check_with_env(&context, baseline, &store, &logins)
+                .await;
+            results.push(TestResult {
+                team: name.clone(),
+                up: output.status.is_up(),
+                status: output.status,
+                fraction: output.fraction,
+                message: output.message,
+                error: output.error,
+                summary: output.summary,
+                metrics: output.metrics,
+                logins: output.logins,
+            });
         }
         Ok(Json(results))
     } else {
@@ -178,6 +208,50 @@ async fn add_service(State(state): State<ConfigState>, Json(payload): Json<Servi
     }
 }
 
+/// GET the service templates in the template catalog
+async fn get_templates() -> Result<Json<Vec<ServiceTemplate>>, (StatusCode, String)> {
+    load_templates()
+        .map(Json)
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err))
+}
+
+#[derive(Deserialize)]
+struct TemplatePayload {
+    /// Name of the new service. The template's name if not given.
+    name: Option<String>,
+    #[serde(default)]
+    params: BTreeMap<String, serde_json::Value>,
+}
+
+/// POST to add a service made from a template with the given parameters.
+/// Returns the new service.
+async fn add_service_from_template(
+    State(state): State<ConfigState>,
+    Path(template): Path<String>,
+    Json(payload): Json<TemplatePayload>,
+) -> Result<Json<Service>, (StatusCode, String)> {
+    let templates = load_templates().map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err))?;
+    let Some(template) = templates.into_iter().find(|t| t.name == template) else {
+        return Err((StatusCode::NOT_FOUND, format!("no template {}", template)));
+    };
+    let name = payload.name.unwrap_or_else(|| template.name.clone());
+    let service = template
+        .instantiate(&name, &payload.params)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+    if !service.is_valid() {
+        let message = format!("{} does not make a valid service", template.name);
+        return Err((StatusCode::BAD_REQUEST, message));
+    }
+    let mut config = state.write().await;
+    match config.add_service(service.clone()) {
+        Ok(_) => Ok(Json(service)),
+        Err(ConfigError::AlreadyExists) => {
+            Err((StatusCode::CONFLICT, format!("service {} already exists", name)))
+        }
+        Err(_) => Err((StatusCode::BAD_REQUEST, format!("{} is not a valid service", name))),
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct EnvPayload {
     name: String,
@@ -185,8 +259,9 @@ struct EnvPayload {
 }
 
 impl EnvPayload {
+    /// Names the scoreboard sets for checkers itself can't be used.
     fn is_valid(&self) -> bool {
-        !self.name.is_empty() && !self.value.is_empty()
+        !self.name.is_empty() && !self.value.is_empty() && !envs::is_reserved_name(&self.name)
     }
 }
 
@@ -256,6 +331,66 @@ async fn add_env(
     }
 }
 
+/// GET the variables every team gets
+async fn get_global_env(State(state): State<ConfigState>) -> Json<Vec<(String, String)>> {
+    Json(state.read().await.env.clone())
+}
+
+/// POST to edit a global environment variable.
+/// The Env Variable must not have empty fields and the name must be unique.
+async fn edit_global_env(
+    State(state): State<ConfigState>,
+    Path(env): Path<String>,
+    Json(payload): Json<EnvPayload>,
+) -> StatusCode {
+    if !payload.is_valid() {
+        return StatusCode::BAD_REQUEST;
+    }
+    let mut config = state.write().await;
+    if env != payload.name && config.env.iter().any(|(name, _)| name == &payload.name) {
+        return StatusCode::CONFLICT;
+    }
+    if let Some(old_env) = config.env.iter_mut().find(|(name, _)| name == &env) {
+        old_env.0 = payload.name;
+        old_env.1 = payload.value;
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// DELETE a global environment variable.
+async fn delete_global_env(
+    State(state): State<ConfigState>,
+    Path(env): Path<String>,
+) -> StatusCode {
+    let mut config = state.write().await;
+    if let Some(index) = config.env.iter().position(|(name, _)| name == &env) {
+        config.env.remove(index);
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// POST to add a global environment variable.
+/// The Env Variable must not have empty fields and the name must be unique.
+async fn add_global_env(
+    State(state): State<ConfigState>,
+    Json(payload): Json<EnvPayload>,
+) -> StatusCode {
+    if !payload.is_valid() {
+        return StatusCode::BAD_REQUEST;
+    }
+    let mut config = state.write().await;
+    if config.env.iter().any(|(name, _)| name == &payload.name) {
+        StatusCode::CONFLICT
+    } else {
+        config.env.push((payload.name, payload.value));
+        StatusCode::OK
+    }
+}
+
 #[derive(Deserialize)]
 struct TeamPayload {
     name: String,
@@ -303,6 +438,38 @@ async fn add_team(
     }
 }
 
+#[derive(Deserialize)]
+struct GeneratePayload {
+    count: u32,
+}
+
+/// POST to add teams made from the team template in the teams config.
+/// Returns the names of the new teams.
+async fn generate_teams(
+    State(state): State<ConfigState>,
+    Json(payload): Json<GeneratePayload>,
+) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    if payload.count == 0 || payload.count > MAX_GENERATED_TEAMS {
+        let message = format!("count should be from 1 to {}", MAX_GENERATED_TEAMS);
+        return Err((StatusCode::BAD_REQUEST, message));
+    }
+    let mut config = state.write().await;
+    match config.generate_teams(payload.count) {
+        Ok(names) => Ok(Json(names)),
+        Err(ConfigError::DoesNotExist) => {
+            Err((StatusCode::NOT_FOUND, "there is no team template".to_string()))
+        }
+        Err(ConfigError::AlreadyExists) => Err((
+            StatusCode::CONFLICT,
+            "a team the template would make already exists".to_string(),
+        )),
+        Err(_) => Err((
+            StatusCode::BAD_REQUEST,
+            "the team template can't make more teams".to_string(),
+        )),
+    }
+}
+
 async fn stop_game(State(state): State<ConfigState>) -> StatusCode {
     let mut config = state.write().await;
     config.stop();
@@ -484,3 +651,83 @@ async fn delete_inject(
     }
 }
 
+
+/// GET a page of the check history, filtered by team, service and time.
+async fn get_history(
+    State(state): State<ConfigState>,
+    Query(query): Query<HistoryQuery>,
+) -> Json<HistoryPage> {
+    let config = state.read().await;
+    Json(config.history.query(&query))
+}
+
+#[derive(Deserialize)]
+struct OutputQuery {
+    team: Option<String>,
+    service: Option<String>,
+}
+
+/// GET the most recent checker outputs, newest first.
+async fn get_outputs(
+    State(state): State<ConfigState>,
+    Query(query): Query<OutputQuery>,
+) -> Json<Vec<ServiceOutputs>> {
+    let config = state.read().await;
+    Json(
+        config
+            .outputs
+            .get(query.team.as_deref(), query.service.as_deref()),
+    )
+}
+
+/// GET the alerts raised by broken checkers
+async fn get_alerts(State(state): State<ConfigState>) -> Json<Vec<Alert>> {
+    let config = state.read().await;
+    Json(config.alerts.clone())
+}
+
+/// DELETE an alert once it has been dealt with
+async fn dismiss_alert(State(state): State<ConfigState>, Path(alert_id): Path<Uuid>) -> StatusCode {
+    let mut config = state.write().await;
+    if config.dismiss_alert(alert_id) {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// GET how many checks are queued, running and have been killed
+async fn get_executor_stats() -> Json<ExecutorStats> {
+    Json(executor().stats())
+}
+
+/// POST to take a new baseline of a team's content for an integrity check
+async fn take_baseline(
+    State(state): State<ConfigState>,
+    Path((service, team)): Path<(String, String)>,
+) -> Result<Json<Baseline>, (StatusCode, String)> {
+    let (service, context) = {
+        let config = state.read().await;
+        let Some(service) = config.services.iter().find(|s| s.name == service) else {
+            return Err((StatusCode::NOT_FOUND, format!("no service {}", service)));
+        };
+        if !service.is_integrity_check() {
+            let message = format!("{} is not an integrity check", service.name);
+            return Err((StatusCode::BAD_REQUEST, message));
+        }
+        let Some(team) = config.teams.get(&team) else {
+            return Err((StatusCode::NOT_FOUND, format!("no team {}", team)));
+        };
+        (service.clone(), config.check_context(team, service))
+    };
+    let baseline = service
+        .take_baseline(&context)
+        .await
+        .map_err(|err| (StatusCode::BAD_GATEWAY, err))?;
+    let mut config = state.write().await;
+    let Some(stored) = config.teams.get_mut(&team) else {
+        return Err((StatusCode::NOT_FOUND, format!("no team {}", team)));
+    };
+    stored.baselines.insert(service.name, baseline.clone());
+    Ok(Json(baseline))
+}
@@ -1,8 +1,8 @@
 use std::collections::HashMap;
 
 use axum::{
-    extract::{Multipart, Path, Request, State},
-    http::{Method, StatusCode},
+    extract::{Multipart, Path, Query, Request, State},
+    http::StatusCode,
     middleware::{self, Next},
     response::Response,
     routing::{get, post},
@@ -14,8 +14,9 @@ use uuid::Uuid;
 
 use crate::{
     checker::{
+        histories::{HistoryPage, HistoryQuery},
         injects::{Inject, InjectResponse, InjectUser},
-        passwords::{get_password_groups, overwrite_passwords}, Score,
+        passwords::{get_password_groups, overwrite_passwords}, Penalty, Score,
     },
     ConfigState,
 };
@@ -64,6 +65,7 @@ pub fn team_router(state: ConfigState) -> Router<ConfigState> {
         .route("/:team/injects", get(get_injects))
         .route("/:team/injects/:inject_uuid", get(get_inject))
         .route("/:team/scores", get(team_scores))
+        .route("/:team/history", get(team_history))
         .layer(middleware::from_fn_with_state(state, check_if_team))
 }
 
@@ -71,21 +73,39 @@ pub fn team_router(state: ConfigState) -> Router<ConfigState> {
 struct TeamScores {
     services: Vec<String>,
     scores: Vec<Score>,
+    /// Weighted points across every service
+    total: f64,
+    /// Raw number of up ticks across every service
+    up_ticks: u32,
+    /// SLA penalties the team has received, oldest first
+    penalties: Vec<Penalty>,
+    /// Last error of each service that is down and allows teams to see it
+    errors: Vec<Option<String>>,
 }
 
 async fn team_scores(
     State(state): State<ConfigState>,
-    Path(team): Path<String>,
+    Path(team_name): Path<String>,
 ) -> Result<Json<TeamScores>, StatusCode> {
     let config = state.read().await;
-    if let Some(team) = config.teams.get(&team) {
+    if let Some(team) = config.teams.get(&team_name) {
         let team_scores = config.services.iter().fold(
             TeamScores {
                 services: Vec::new(),
                 scores: Vec::new(),
+                total: team.score(),
+                up_ticks: team.up_ticks(),
+                penalties: team.penalties.clone(),
+                errors: Vec::new(),
             },
             |mut acc, s| {
                 acc.services.push(s.name.clone());
+                acc.errors.push(if s.show_errors {
+                    let env = config.check_env(team, s);
+                    config.outputs.last_error(&team_name, &s.name, &env)
+                } else {
+                    None
+                });
                 acc.scores.push(
                     team.scores
                         .get(&s.name)
@@ -101,6 +121,19 @@ async fn team_scores(
     }
 }
 
+async fn team_history(
+    State(state): State<ConfigState>,
+    Path(team): Path<String>,
+    Query(mut query): Query<HistoryQuery>,
+) -> Result<Json<HistoryPage>, StatusCode> {
+    let config = state.read().await;
+    if !config.teams.contains_key(&team) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    query.team = Some(team);
+    Ok(Json(config.history.query(&query)))
+}
+
 async fn get_team_pw(Path(team): Path<String>) -> Result<Json<Vec<String>>, StatusCode> {
     if let Ok(groups) = get_password_groups(&team) {
         Ok(Json(groups))
@@ -209,7 +242,7 @@ async fn get_injects(
         .ok_or(StatusCode::NOT_FOUND)?
         .iter()
         .cloned()
-        .map(|inject| InjectDesc::from_inject(inject))
+        .map(InjectDesc::from_inject)
         .collect();
     Ok(Json(InjectRequest {
         active_injects,
@@ -233,7 +266,7 @@ async fn get_inject(
         .get_inject(inject_uuid)
         .ok_or(StatusCode::NOT_FOUND)?;
     let team = config.teams.get(&team).ok_or(StatusCode::NOT_FOUND)?;
-    let html = inject.get_html(&team.env);
+    let html = inject.get_html(&config.team_env(team));
     let history = team.get_reponses(inject_uuid);
     Ok(Json(InjectData {
         desc: InjectDesc::from_inject(inject),